@@ -9,10 +9,9 @@
 use std::io::Write;
 use std::fs::OpenOptions;
 use serde::Serialize;
-use std::cell::RefCell;
 use std::fmt::Debug;
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 use structopt::StructOpt;
 use rayon::prelude::*;
@@ -21,6 +20,7 @@ use url::Url;
 use airindex::common::error::GResult;
 use airindex::db::key_rank::SOSDRankDB;
 use airindex::io::internal::ExternalStorage;
+use airindex::io::profile;
 use airindex::io::profile::AffineStorageProfile;
 use airindex::io::profile::Bandwidth;
 use airindex::io::profile::Latency;
@@ -215,6 +215,9 @@ pub struct Cli {
   /// manual storage profile's bandwidth in MB/s (affine)
   #[structopt(long, default_value = "100.0")]  // 100 MB/s
   affine_bandwidth_mbps: f64,
+  /// storage profile in json (any profile kind), preferred over affine params if given
+  #[structopt(long)]
+  profile_path: Option<String>,
   /// lowerbound to fanout hyperparameters
   #[structopt(long, default_value = "16")]  // 256 / 16
   fanout_min: usize,
@@ -243,7 +246,7 @@ pub struct DataCalculatorResult<'a> {
 /* Experiment scope */
 
 struct Experiment {
-  storage: Rc<RefCell<ExternalStorage>>,
+  storage: Arc<ExternalStorage>,
   sosd_context: Context,
   sosd_blob_name: String,
 }
@@ -261,7 +264,7 @@ impl std::fmt::Debug for Experiment {
 impl Experiment {
   pub fn from(args: &Cli) -> GResult<Experiment> {
     // common external storage
-    let es = Rc::new(RefCell::new(Experiment::load_io()?));
+    let es = Arc::new(Experiment::load_io()?);
 
     // create context for sosd dataset
     let sosd_blob_url = Url::parse(&args.sosd_blob_url)?;
@@ -300,7 +303,7 @@ impl Experiment {
 
   pub fn build(&mut self, args: &Cli) -> GResult<(DataLayout, Duration)> {
     // load storage profile
-    let profile = self.load_profile(args);
+    let profile = self.load_profile(args)?;
 
     // load dataset and generate the first key-position pairs
     let sosd_db = self.load_new_sosd(args)?;
@@ -337,11 +340,16 @@ impl Experiment {
     Ok(SOSDRankDB::new(array_store))
   }
 
-  fn load_profile(&self, args: &Cli) -> Box<dyn StorageProfile> {
-    Box::new(AffineStorageProfile::new(
+  fn load_profile(&self, args: &Cli) -> GResult<Box<dyn StorageProfile>> {
+    if let Some(profile_path) = &args.profile_path {
+      let profile = profile::load_profile(&PathBuf::from(profile_path))?;
+      log::info!("Loaded {:?}", profile);
+      return Ok(profile);
+    }
+    Ok(Box::new(AffineStorageProfile::new(
       Latency::from_nanos(args.affine_latency_ns),
       Bandwidth::from_mbps(args.affine_bandwidth_mbps)
-    ))
+    )))
   }
 
   fn build_index_from_keys(&self, args: &Cli, data_kps: &[KeyT], profile: &dyn StorageProfile) -> (DataLayout, Duration) {
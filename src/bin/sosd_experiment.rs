@@ -1,9 +1,8 @@
 use serde::Serialize;
-use std::cell::RefCell;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 use structopt::StructOpt;
@@ -21,15 +20,40 @@ use airindex::index::hierarchical::BoundedTopStackIndexBuilder;
 use airindex::index::hierarchical::ExploreStackIndexBuilder;
 use airindex::index::Index;
 use airindex::index::IndexBuilder;
+use airindex::io::cache::CachePolicy;
+use airindex::io::calibrate::Calibration;
+use airindex::io::calibrate::CalibrationConfig;
+use airindex::io::calibrate::calibrate;
+use airindex::io::disk_cache::DiskCache;
+use airindex::io::internal::CacheStats;
 use airindex::io::internal::ExternalStorage;
+use airindex::io::price::AffinePriceProfile;
+use airindex::io::price::PriceProfile;
+use airindex::io::price::price_per_million_lookups;
+use airindex::io::profile;
 use airindex::io::profile::AffineStorageProfile;
 use airindex::io::profile::Bandwidth;
 use airindex::io::profile::Latency;
 use airindex::io::profile::StorageProfile;
+use airindex::io::profile::WeightedStorageProfile;
+use airindex::io::retry::new_retry_stats;
+use airindex::io::retry::RetryAdaptor;
+use airindex::io::retry::RetryPolicy;
+use airindex::io::retry::RetryStats;
+use airindex::io::retry::SharedRetryStats;
 use airindex::io::storage::Adaptor;
 use airindex::io::storage::AzureStorageAdaptor;
+use airindex::io::storage::EMULATED_SCHEME_PREFIX;
+use airindex::io::storage::EmulatedAdaptor;
 use airindex::io::storage::FileSystemAdaptor;
+use airindex::io::storage::HttpAdaptor;
+use airindex::io::storage::MemoryAdaptor;
 use airindex::io::storage::MmapAdaptor;
+use airindex::io::storage::S3StorageAdaptor;
+use airindex::io::storage::http_client_with_timeout;
+use airindex::io::trace::RecordingAdaptor;
+use airindex::io::trace::SharedTraceRecorder;
+use airindex::io::trace::TraceRecorder;
 use airindex::meta::Context;
 use airindex::meta;
 use airindex::model::band::BandMultipleDrafter;
@@ -60,6 +84,9 @@ pub struct Cli {
   /// action: breakdown latency
   #[structopt(long)]
   do_breakdown: bool,
+  /// action: calibrate storage profile against the sosd blob, runs before build
+  #[structopt(long)]
+  do_calibrate: bool,
 
   /// dataset name [blob]
   #[structopt(long)]
@@ -99,6 +126,15 @@ pub struct Cli {
   /// manual storage profile's bandwidth in MB/s (affine)
   #[structopt(long, default_value = "100.0")]  // 100 MB/s
   affine_bandwidth_mbps: f64,
+  /// storage profile in json (any profile kind), replaces affine params, written by calibrate
+  #[structopt(long)]
+  profile_path: Option<String>,
+  /// number of timed reads per read size in calibration
+  #[structopt(long, default_value = "8")]
+  calibrate_reads: usize,
+  /// largest read size in calibration in MB
+  #[structopt(long, default_value = "16")]
+  calibrate_max_read_mb: usize,
   /// lowerbound to load hyperparameters
   #[structopt(long, default_value = "256")]
   low_load: usize,
@@ -117,6 +153,44 @@ pub struct Cli {
   /// top-k candidates to select at each branching
   #[structopt(long)]
   top_k_candidates: Option<usize>,
+  /// latency of a page cache hit in nanoseconds, enables cache-aware tuning (enb index only)
+  #[structopt(long)]
+  cache_hit_latency_ns: Option<u64>,
+
+
+  /* remote storage params */
+
+  /// max attempts per request to remote storage, including the first
+  #[structopt(long, default_value = "5")]
+  retry_max_attempts: usize,
+  /// timeout per request to remote storage in milliseconds
+  #[structopt(long, default_value = "30000")]  // 30 s
+  request_timeout_ms: u64,
+  /// local directory to persist pages of remote blobs across runs
+  #[structopt(long)]
+  disk_cache_dir: Option<String>,
+  /// size limit of the disk cache in MB
+  #[structopt(long, default_value = "16384")]  // 16 GB
+  disk_cache_size_mb: usize,
+  /// price of 1000 read requests in dollars, enables price report per million lookups
+  #[structopt(long)]
+  price_per_kilo_requests: Option<f64>,
+  /// price of reading 1 GB in dollars
+  #[structopt(long, default_value = "0.0")]
+  price_per_gb: f64,
+  /// latency in seconds worth one dollar, tunes index for latency plus weighted price if positive
+  #[structopt(long, default_value = "0.0")]
+  price_weight: f64,
+
+
+  /* local storage params */
+
+  /// madvise hints for mmap:// files [random, willneed, hugepage]
+  #[structopt(long, use_delimiter = true)]
+  mmap_advices: Vec<String>,
+  /// prefault whole mmap:// files when first mapped
+  #[structopt(long)]
+  mmap_populate: bool,
 
 
   /* For testing/debugging */
@@ -124,6 +198,18 @@ pub struct Cli {
   /// disable cache to storage IO interface
   #[structopt(long)]
   no_cache: bool,
+  /// page cache eviction policy [fifo, lru, clock, 2q]
+  #[structopt(long, default_value = "fifo")]
+  cache_policy: String,
+  /// emulated storage's latency in nanoseconds, served under emu+file://
+  #[structopt(long)]
+  emulate_latency_ns: Option<u64>,
+  /// emulated storage's bandwidth in MB/s, served under emu+file://
+  #[structopt(long)]
+  emulate_bandwidth_mbps: Option<f64>,
+  /// path to write io trace in jsonl, see trace_replay
+  #[structopt(long)]
+  trace_path: Option<String>,
   /// disable parallel index building
   #[structopt(long)]
   no_parallel: bool,
@@ -140,6 +226,15 @@ pub struct BenchmarkResult<'a> {
   setting: &'a Cli,
   time_measures: &'a [u128],
   query_counts: &'a [usize],
+  retry_stats: &'a RetryStats,
+  cache_stats: &'a CacheStats,
+  price_per_million_lookups: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct CalibrationResult<'a> {
+  setting: &'a Cli,
+  calibration: &'a Calibration,
 }
 
 #[derive(Serialize)]
@@ -153,11 +248,13 @@ pub struct BreakdownResult<'a> {
 /* Experiment scope */
 
 struct Experiment {
-  storage: Rc<RefCell<ExternalStorage>>,
+  storage: Arc<ExternalStorage>,
   sosd_context: Context,
   db_context: Context,
   sosd_blob_name: String,
   keyset_url: Url,
+  recorder: Option<SharedTraceRecorder>,
+  retry_stats: SharedRetryStats,
 }
 
 impl std::fmt::Debug for Experiment {
@@ -174,17 +271,27 @@ impl std::fmt::Debug for Experiment {
 
 impl Experiment {
   pub fn from(args: &Cli) -> GResult<Experiment> {
+    // trace io requests if requested
+    let recorder = match &args.trace_path {
+      Some(trace_path) => Some(TraceRecorder::create(&PathBuf::from(trace_path))?),
+      None => None,
+    };
+
     // common external storage
-    let es = Rc::new(RefCell::new(Experiment::load_io(args)?));
+    let retry_stats = new_retry_stats();
+    let mut es = Experiment::load_io(args, &recorder, &retry_stats)?;
+    let sosd_blob_url = Url::parse(&args.sosd_blob_url)?;
+    let db_url = Url::parse(&(args.db_url.clone() + "/"))?;  // enforce directory
+    es.track_prefix(&sosd_blob_url);
+    es.track_prefix(&db_url);
+    let es = Arc::new(es);
 
     // create context for sosd dataset
-    let sosd_blob_url = Url::parse(&args.sosd_blob_url)?;
     let mut sosd_context = Context::new();
     sosd_context.put_storage(&es);
     sosd_context.put_store_prefix(&sosd_blob_url.join(".")?);
 
     // create data context for sosd rank db
-    let db_url = Url::parse(&(args.db_url.clone() + "/"))?;  // enforce directory
     let mut db_context = Context::new();
     db_context.put_storage(&es);
     db_context.put_store_prefix(&db_url);
@@ -195,38 +302,99 @@ impl Experiment {
       db_context,
       sosd_blob_name: PathBuf::from(sosd_blob_url.path()).file_name().unwrap().to_str().unwrap().to_string(),
       keyset_url: Url::parse(&args.keyset_url)?,
+      recorder,
+      retry_stats,
     })
   }
 
-  fn load_io(args: &Cli) -> GResult<ExternalStorage> {
+  fn load_io(args: &Cli, recorder: &Option<SharedTraceRecorder>, retry_stats: &SharedRetryStats) -> GResult<ExternalStorage> {
+    let cache_policy: CachePolicy = args.cache_policy.parse()?;
+    let page_size = 1 << 12;  // 4096
     let mut es = if args.no_cache {
-      ExternalStorage::new_with_cache(0, 4096)  // cache of size 0 byte
+      ExternalStorage::new_with_cache(0, page_size, cache_policy)  // cache of size 0 byte
     } else {
-      ExternalStorage::new()
+      ExternalStorage::new_with_cache(1 << 33 /* 8 GB */, page_size, cache_policy)
     };
+    if let Some(disk_cache_dir) = &args.disk_cache_dir {
+      let disk_cache_size = if args.no_cache { 0 } else { args.disk_cache_size_mb << 20 };
+      let disk_cache = DiskCache::open(&PathBuf::from(disk_cache_dir), disk_cache_size, page_size, cache_policy)?;
+      let remote_schemes = ["az", "s3", "http", "https", "emu+file"];
+      es.set_disk_cache(disk_cache, remote_schemes.iter().map(|scheme| scheme.to_string()).collect());
+    }
+    if let Some(recorder) = recorder {
+      es.set_trace_recorder(Arc::clone(recorder));
+    }
+    es.set_profile(Experiment::load_profile(args)?);
 
     // file system
     let fsa = Box::new(FileSystemAdaptor::new()) as Box<dyn Adaptor>;
-    es = es.with("file".to_string(), fsa)?;
+    es = es.with("file".to_string(), Experiment::traced(fsa, recorder))?;
 
     // file system, via mmap
-    let mfsa = Box::new(MmapAdaptor::new()) as Box<dyn Adaptor>;
-    es = es.with("mmap".to_string(), mfsa)?;
+    let mut mfsa = MmapAdaptor::new().with_populate(args.mmap_populate);
+    for advice in &args.mmap_advices {
+      mfsa = mfsa.with_advice(advice.parse()?);
+    }
+    let mfsa = Box::new(mfsa) as Box<dyn Adaptor>;
+    es = es.with("mmap".to_string(), Experiment::traced(mfsa, recorder))?;
+
+    // in-memory, lives only within this process
+    es = es.with("mem".to_string(), Experiment::traced(Box::new(MemoryAdaptor::new()), recorder))?;
+
+    // remote storages retry transient failures, their clients time out each request
+    let policy = RetryPolicy::default().max_attempts(args.retry_max_attempts);
+    let http_client = http_client_with_timeout(Duration::from_millis(args.request_timeout_ms));
+    let remote = |adaptor: Box<dyn Adaptor>| {
+      let rtya = RetryAdaptor::new(adaptor, policy.clone(), Arc::clone(retry_stats));
+      Experiment::traced(Box::new(rtya), recorder)
+    };
 
     // azure storage
     let aza = AzureStorageAdaptor::new_block();
     match aza {
-      Ok(aza) => es = es.with("az".to_string(), Box::new(aza))?,
+      Ok(aza) => es = es.with("az".to_string(), remote(Box::new(aza.http_client(http_client.clone()))))?,
       Err(e) => log::warn!("Failed to initialize azure storage, {:?}", e),
     }
 
+    // s3-compatible storage
+    let s3a = S3StorageAdaptor::new();
+    match s3a {
+      Ok(s3a) => es = es.with("s3".to_string(), remote(Box::new(s3a.http_client(http_client.clone()))))?,
+      Err(e) => log::warn!("Failed to initialize s3 storage, {:?}", e),
+    }
+
+    // read-only http(s), e.g. static file server
+    es = es.with("http".to_string(), remote(Box::new(HttpAdaptor::new().http_client(http_client.clone()))))?;
+    es = es.with("https".to_string(), remote(Box::new(HttpAdaptor::new().http_client(http_client.clone()))))?;
+
+    // file system, throttled to emulate remote storage
+    if args.emulate_latency_ns.is_some() || args.emulate_bandwidth_mbps.is_some() {
+      let profile = AffineStorageProfile::new(
+        Latency::from_nanos(args.emulate_latency_ns.unwrap_or(0)),
+        Bandwidth::from_mbps(args.emulate_bandwidth_mbps.unwrap_or(f64::INFINITY)),
+      );
+      log::info!("Emulating file system with {:?}", profile);
+      let emua = EmulatedAdaptor::new(Box::new(FileSystemAdaptor::new()), Box::new(profile));
+      es = es.with(format!("{}file", EMULATED_SCHEME_PREFIX), Experiment::traced(Box::new(emua), recorder))?;
+    }
+
     Ok(es)
       
   }
 
+  fn traced(adaptor: Box<dyn Adaptor>, recorder: &Option<SharedTraceRecorder>) -> Box<dyn Adaptor> {
+    match recorder {
+      Some(recorder) => Box::new(RecordingAdaptor::new(adaptor, Arc::clone(recorder))),
+      None => adaptor,
+    }
+  }
+
   pub fn build(&mut self, args: &Cli) -> GResult<()> {
-    // load storage profile
-    let profile = self.load_profile(args);
+    // load storage profile, weighted with price if asked
+    let profile = match (Experiment::load_price(args), args.price_weight > 0.0) {
+      (Some(price), true) => Box::new(WeightedStorageProfile::new(Experiment::load_profile(args)?, price, args.price_weight)),
+      _ => Experiment::load_profile(args)?,
+    };
 
     // load dataset and generate the first key-position pairs
     let mut sosd_db = self.load_new_sosd(args)?;
@@ -247,7 +415,6 @@ impl Experiment {
 
     // write metadata
     self.db_context.storage.as_ref().unwrap()
-      .borrow()
       .write_all(&self.db_meta()?, &meta_bytes)?;
 
     Ok(())
@@ -265,22 +432,58 @@ impl Experiment {
       self.sosd_context.storage.as_ref().unwrap(),
       self.sosd_context.store_prefix.as_ref().unwrap().clone(),
       self.sosd_blob_name.clone(),
-      match args.sosd_dtype.as_str() {
-        "uint32" => 4,
-        "uint64" => 8,
-        _ => panic!("Invalid sosd dtype \"{}\"", args.sosd_dtype),
-      },
+      Experiment::sosd_dtype_size(args),
       8,  // SOSD array leads with 8-byte encoding of the length
       args.sosd_size * 1_000_000,
     );
     Ok(SOSDRankDB::new(array_store))
   }
 
-  fn load_profile(&self, args: &Cli) -> Box<dyn StorageProfile> {
-    Box::new(AffineStorageProfile::new(
+  fn sosd_dtype_size(args: &Cli) -> usize {
+    match args.sosd_dtype.as_str() {
+      "uint32" => 4,
+      "uint64" => 8,
+      _ => panic!("Invalid sosd dtype \"{}\"", args.sosd_dtype),
+    }
+  }
+
+  fn load_profile(args: &Cli) -> GResult<Box<dyn StorageProfile>> {
+    if let Some(profile_path) = &args.profile_path {
+      // before calibration writes the profile, affine params stand in
+      if !args.do_calibrate || PathBuf::from(profile_path).exists() {
+        let profile = profile::load_profile(&PathBuf::from(profile_path))?;
+        log::info!("Loaded {:?}", profile);
+        return Ok(profile);
+      }
+    }
+    Ok(Box::new(AffineStorageProfile::new(
       Latency::from_nanos(args.affine_latency_ns),
       Bandwidth::from_mbps(args.affine_bandwidth_mbps)
-    ))
+    )))
+  }
+
+  fn load_price(args: &Cli) -> Option<Box<dyn PriceProfile>> {
+    args.price_per_kilo_requests.map(|price_per_kilo_requests| {
+      Box::new(AffinePriceProfile::from_listed(price_per_kilo_requests, args.price_per_gb)) as Box<dyn PriceProfile>
+    })
+  }
+
+  pub fn calibrate(&self, args: &Cli) -> GResult<Calibration> {
+    // timed reads directly on the adaptor, bypassing caches
+    let sosd_blob_url = Url::parse(&args.sosd_blob_url)?;
+    let blob_size = 8 + Experiment::sosd_dtype_size(args) * args.sosd_size * 1_000_000;
+    let config = CalibrationConfig {
+      max_read_size: args.calibrate_max_read_mb << 20,
+      reads_per_size: args.calibrate_reads,
+      ..CalibrationConfig::default()
+    };
+    let adaptor = self.storage.select_adaptor(&sosd_blob_url)?;
+    let calibration = calibrate(adaptor.as_ref().as_ref(), &sosd_blob_url, blob_size, &config)?;
+    if let Some(profile_path) = &args.profile_path {
+      profile::save_profile(&PathBuf::from(profile_path), &calibration.profile)?;
+      log::info!("Wrote calibrated profile to {}", profile_path);
+    }
+    Ok(calibration)
   }
 
   fn build_index_from_kps(&self, args: &Cli, data_kps: &KeyPositionCollection, profile: &dyn StorageProfile) -> GResult<Box<dyn Index>> {
@@ -338,6 +541,9 @@ impl Experiment {
         if let Some(top_k_candidates) = args.top_k_candidates {
           enb = enb.set_top_k_candidates(top_k_candidates);
         }
+        if let Some(cache_hit_latency_ns) = args.cache_hit_latency_ns {
+          enb = enb.set_cache_hit_profile(Box::new(Latency::from_nanos(cache_hit_latency_ns)));
+        }
         Box::new(enb)
       },
       "enb_layers" => {
@@ -352,6 +558,9 @@ impl Experiment {
         if let Some(top_k_candidates) = args.top_k_candidates {
           enb = enb.set_top_k_candidates(top_k_candidates);
         }
+        if let Some(cache_hit_latency_ns) = args.cache_hit_latency_ns {
+          enb = enb.set_cache_hit_profile(Box::new(Latency::from_nanos(cache_hit_latency_ns)));
+        }
         Box::new(enb)
       },
       "btree" => {
@@ -399,12 +608,16 @@ impl Experiment {
     let start_time = Instant::now();
     tracing::trace!("sosd_setup");
     log::debug!("Benchmark started");
+    self.storage.reset_stats();
 
     // reload data structure
     let sosd_db = self.reload()?;
     tracing::trace!("sosd_reload");
     log::debug!("Reloaded rank db");
     for (idx, test_kr) in test_keyset.iter().enumerate().take(num_samples) {
+      if let Some(recorder) = &self.recorder {
+        recorder.lock().unwrap().begin_query(idx);
+      }
       let rcv_kr = sosd_db.rank_of(test_kr.key)?
         .unwrap_or_else(|| panic!("Existing key {} not found", test_kr.key));
       assert_eq!(rcv_kr, *test_kr, "Mismatch rank rcv: {:?}, actual: {:?}", rcv_kr, test_kr);
@@ -426,7 +639,13 @@ impl Experiment {
       }
       tracing::trace!("complete_query");
     }
+    if let Some(recorder) = &self.recorder {
+      recorder.lock().unwrap().end_query();
+      recorder.lock().unwrap().flush()?;
+    }
     log::info!("Benchmarked {:#?}", sosd_db);
+    log::info!("Remote storage retries: {:?}", self.retry_stats.lock().unwrap());
+    log::info!("Cache stats: {:#?}", self.storage.stats());
     Ok((time_measures, query_counts))
   }
 
@@ -500,13 +719,12 @@ impl Experiment {
   }
 
   fn load_keyset(&self) -> GResult<Vec<KeyRank>> {
-    let keyset_bytes = self.storage.borrow().read_all(&self.keyset_url)?;
+    let keyset_bytes = self.storage.read_all(&self.keyset_url)?;
     read_keyset(&keyset_bytes[..])
   }
 
   fn reload(&self) -> GResult<SOSDRankDB> {
     let meta_bytes = self.db_context.storage.as_ref().unwrap()
-      .borrow()
       .read_all(&self.db_meta()?)?;
     tracing::trace!("sosd_readmeta");
     log::trace!("Loaded metadata of {} bytes", meta_bytes.len());
@@ -535,6 +753,12 @@ fn main_guarded() -> GResult<()> {
   let mut exp = Experiment::from(&args)?;
   log::info!("{:?}", exp);
 
+  // calibrate storage profile
+  if args.do_calibrate {
+    let calibration = exp.calibrate(&args)?;
+    log_result_calibration(&args, &calibration)?;
+  }
+
   // build index
   if args.do_build {
     exp.build(&args)?;
@@ -547,7 +771,16 @@ fn main_guarded() -> GResult<()> {
     let (time_measures, query_counts) = exp.benchmark(&args, test_keyset)?;
     log::info!("Collected {} measurements", time_measures.len()); 
     assert_eq!(time_measures.len(), query_counts.len());
-    log_result(&args, &time_measures, &query_counts)?;
+
+    // all adaptor reads in benchmark are billed, including reload
+    let cache_stats = exp.storage.stats();
+    let price_per_million = Experiment::load_price(&args).map(|price| {
+      price_per_million_lookups(price.as_ref(), &cache_stats.total, query_counts.last().copied().unwrap_or(0))
+    });
+    if let Some(price_per_million) = price_per_million {
+      log::info!("Expected price per million lookups: ${:.4}", price_per_million);
+    }
+    log_result(&args, &time_measures, &query_counts, &exp.retry_stats.lock().unwrap(), &cache_stats, price_per_million)?;
   };
 
   // inspect
@@ -566,12 +799,31 @@ fn main_guarded() -> GResult<()> {
   Ok(())
 }
 
-fn log_result(args: &Cli, time_measures: &[u128], query_counts: &[usize]) -> GResult<()> {
+fn log_result(
+  args: &Cli,
+  time_measures: &[u128],
+  query_counts: &[usize],
+  retry_stats: &RetryStats,
+  cache_stats: &CacheStats,
+  price_per_million_lookups: Option<f64>,
+) -> GResult<()> {
   // compose json result
   let result_json = serde_json::to_string(&BenchmarkResult {
     setting: args,
     time_measures,
     query_counts,
+    retry_stats,
+    cache_stats,
+    price_per_million_lookups,
+  })?;
+  write_json(args, result_json)
+}
+
+fn log_result_calibration(args: &Cli, calibration: &Calibration) -> GResult<()> {
+  // compose json result
+  let result_json = serde_json::to_string(&CalibrationResult {
+    setting: args,
+    calibration,
   })?;
   write_json(args, result_json)
 }
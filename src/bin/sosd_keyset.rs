@@ -1,6 +1,5 @@
 use serde::Serialize;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 use structopt::StructOpt;
 
 use airindex::common::error::GResult;
@@ -80,7 +79,7 @@ fn load_sosd(args: &Cli) -> GResult<SOSDRankDB> {
   // prepare storage interface
   let root_url = url_from_dir_path(&std::env::current_dir()?)?;
   let fsa = Box::new(FileSystemAdaptor::new());
-  let es = Rc::new(RefCell::new(ExternalStorage::new().with("file".to_string(), fsa)?));
+  let es = Arc::new(ExternalStorage::new().with("file".to_string(), fsa)?);
 
   let array_store = ArrayStore::from_exact(
     &es,
@@ -0,0 +1,98 @@
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use airindex::common::error::GResult;
+use airindex::io::profile;
+use airindex::io::profile::AffineStorageProfile;
+use airindex::io::profile::Bandwidth;
+use airindex::io::profile::Latency;
+use airindex::io::profile::StorageProfile;
+use airindex::io::trace::QueryReplay;
+use airindex::io::trace::read_trace;
+use airindex::io::trace::replay;
+use airindex::io::trace::total_duration;
+
+
+/* Parsed arguments */
+
+#[derive(Debug, Serialize, StructOpt)]
+pub struct Cli {
+  /// path to io trace in jsonl, recorded by sosd_experiment --trace-path
+  #[structopt(long)]
+  trace_path: String,
+  /// output path to write per-query replay in jsonl
+  #[structopt(long)]
+  out_path: Option<String>,
+
+  /// storage profile's latency in nanoseconds (affine)
+  #[structopt(long, default_value = "10000000")]  // 10 ms
+  affine_latency_ns: u64,
+  /// storage profile's bandwidth in MB/s (affine)
+  #[structopt(long, default_value = "100.0")]  // 100 MB/s
+  affine_bandwidth_mbps: f64,
+  /// storage profile in json (any profile kind), preferred over affine params if given
+  #[structopt(long)]
+  profile_path: Option<String>,
+}
+
+
+fn main_guarded() -> GResult<()> {
+  // execution init
+  env_logger::Builder::from_default_env()
+    .format_timestamp_micros()
+    .init();
+
+  // parse args
+  let args = Cli::from_args();
+  log::info!("{:?}", args);
+
+  // replay trace against the profile
+  let profile: Box<dyn StorageProfile> = match &args.profile_path {
+    Some(profile_path) => profile::load_profile(&PathBuf::from(profile_path))?,
+    None => Box::new(AffineStorageProfile::new(
+      Latency::from_nanos(args.affine_latency_ns),
+      Bandwidth::from_mbps(args.affine_bandwidth_mbps),
+    )),
+  };
+  log::info!("Replaying against {:?}", profile);
+  let events = read_trace(&PathBuf::from(&args.trace_path))?;
+  let replays = replay(&events, profile.as_ref());
+  log::info!("Replayed {} queries from {} events", replays.len(), events.len());
+
+  // summarize
+  if !replays.is_empty() {
+    let (measured, predicted) = total_duration(&replays);
+    let num_queries = replays.len() as u32;
+    log::info!(
+      "measured {:>9.2?}/op, predicted {:>9.2?}/op, ratio {:.3}",
+      measured / num_queries,
+      predicted / num_queries,
+      measured.as_secs_f64() / predicted.as_secs_f64(),
+    );
+  }
+
+  if let Some(out_path) = &args.out_path {
+    write_replays(out_path, &replays)?;
+  }
+  Ok(())
+}
+
+fn write_replays(out_path: &str, replays: &[QueryReplay]) -> GResult<()> {
+  let mut file = OpenOptions::new()
+    .create(true)
+    .write(true)
+    .truncate(true)
+    .open(out_path)?;
+  for query_replay in replays {
+    writeln!(&mut file, "{}", serde_json::to_string(query_replay)?)?;
+  }
+  log::info!("Wrote replay to {:?}", out_path);
+  Ok(())
+}
+
+fn main() {
+  main_guarded().expect("Error occur during trace replay");
+}
@@ -57,6 +57,102 @@ unsafe impl Send for InvalidAzureStorageUrl {}
 unsafe impl Sync for InvalidAzureStorageUrl {}
 
 
+#[derive(Display, Debug, Clone)]
+pub struct MissingS3Authentication {
+  reason: String,
+}
+impl MissingS3Authentication {
+  pub fn boxed(reason: &str) -> GenericError {
+    Box::new(MissingS3Authentication { reason: reason.to_string() })
+  }
+}
+impl Error for MissingS3Authentication {}
+unsafe impl Send for MissingS3Authentication {}
+unsafe impl Sync for MissingS3Authentication {}
+
+
+#[derive(Display, Debug, Clone)]
+pub struct InvalidS3StorageUrl {
+  reason: String,
+}
+impl InvalidS3StorageUrl {
+  pub fn boxed(reason: &str) -> GenericError {
+    Box::new(InvalidS3StorageUrl { reason: reason.to_string() })
+  }
+}
+impl Error for InvalidS3StorageUrl {}
+unsafe impl Send for InvalidS3StorageUrl {}
+unsafe impl Sync for InvalidS3StorageUrl {}
+
+
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Request to {} failed with status {}, {}", url, status, reason)]
+pub struct HttpStatusError {
+  url: String,
+  status: u16,
+  reason: String,
+}
+impl HttpStatusError {
+  pub fn boxed(url: String, status: u16, reason: String) -> GenericError {
+    Box::new(HttpStatusError { url, status, reason })
+  }
+
+  pub fn status(&self) -> u16 {
+    self.status
+  }
+}
+impl Error for HttpStatusError {}
+unsafe impl Send for HttpStatusError {}
+unsafe impl Sync for HttpStatusError {}
+
+
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Server of {} ignored range request, responded with status {}", url, status)]
+pub struct UnsupportedRangeRequest {
+  url: String,
+  status: u16,
+}
+impl UnsupportedRangeRequest {
+  pub fn boxed(url: String, status: u16) -> GenericError {
+    Box::new(UnsupportedRangeRequest { url, status })
+  }
+}
+impl Error for UnsupportedRangeRequest {}
+unsafe impl Send for UnsupportedRangeRequest {}
+unsafe impl Sync for UnsupportedRangeRequest {}
+
+
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Storage at {} is read-only", url)]
+pub struct ReadOnlyStorage {
+  url: String,
+}
+impl ReadOnlyStorage {
+  pub fn boxed(url: String) -> GenericError {
+    Box::new(ReadOnlyStorage { url })
+  }
+}
+impl Error for ReadOnlyStorage {}
+unsafe impl Send for ReadOnlyStorage {}
+unsafe impl Sync for ReadOnlyStorage {}
+
+
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Url {} is not under emulated scheme {}", url, scheme)]
+pub struct InvalidEmulatedStorageUrl {
+  url: String,
+  scheme: String,
+}
+impl InvalidEmulatedStorageUrl {
+  pub fn boxed(url: String, scheme: &str) -> GenericError {
+    Box::new(InvalidEmulatedStorageUrl { url, scheme: scheme.to_string() })
+  }
+}
+impl Error for InvalidEmulatedStorageUrl {}
+unsafe impl Send for InvalidEmulatedStorageUrl {}
+unsafe impl Sync for InvalidEmulatedStorageUrl {}
+
+
 /* External Store */
 
 #[derive(Display, Debug, Clone)]
@@ -89,6 +185,52 @@ unsafe impl Send for UnavailableStorageScheme {}
 unsafe impl Sync for UnavailableStorageScheme {}
 
 
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Unknown cache policy {}, expected one of [fifo, lru, clock, 2q]", policy)]
+pub struct UnknownCachePolicy {
+  policy: String,
+}
+impl UnknownCachePolicy {
+  pub fn boxed(policy: &str) -> GenericError {
+    Box::new(UnknownCachePolicy { policy: policy.to_string() })
+  }
+}
+impl Error for UnknownCachePolicy {}
+unsafe impl Send for UnknownCachePolicy {}
+unsafe impl Sync for UnknownCachePolicy {}
+
+
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Unknown mmap advice {}, expected one of [random, willneed, hugepage]", advice)]
+pub struct UnknownMmapAdvice {
+  advice: String,
+}
+impl UnknownMmapAdvice {
+  pub fn boxed(advice: &str) -> GenericError {
+    Box::new(UnknownMmapAdvice { advice: advice.to_string() })
+  }
+}
+impl Error for UnknownMmapAdvice {}
+unsafe impl Send for UnknownMmapAdvice {}
+unsafe impl Sync for UnknownMmapAdvice {}
+
+
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Unable to calibrate on {}, {}", url, reason)]
+pub struct CalibrationError {
+  url: String,
+  reason: String,
+}
+impl CalibrationError {
+  pub fn boxed(url: String, reason: &str) -> GenericError {
+    Box::new(CalibrationError { url, reason: reason.to_string() })
+  }
+}
+impl Error for CalibrationError {}
+unsafe impl Send for CalibrationError {}
+unsafe impl Sync for CalibrationError {}
+
+
 /* Stores */
 
 #[derive(Display, Debug, Clone)]
@@ -105,6 +247,55 @@ unsafe impl Send for IncompleteDataStoreFromMeta {}
 unsafe impl Sync for IncompleteDataStoreFromMeta {}
 
 
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Key buffers must have strictly increasing keys, got {} after {}", key, prev_key)]
+pub struct UnsortedKeyBuffers {
+  prev_key: u64,
+  key: u64,
+}
+impl UnsortedKeyBuffers {
+  pub fn boxed(prev_key: u64, key: u64) -> GenericError {
+    Box::new(UnsortedKeyBuffers { prev_key, key })
+  }
+}
+impl Error for UnsortedKeyBuffers {}
+unsafe impl Send for UnsortedKeyBuffers {}
+unsafe impl Sync for UnsortedKeyBuffers {}
+
+
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Store requires entries of {} bytes, got {} bytes for key {}", expected, actual, key)]
+pub struct MismatchedDataSize {
+  key: u64,
+  expected: usize,
+  actual: usize,
+}
+impl MismatchedDataSize {
+  pub fn boxed(key: u64, expected: usize, actual: usize) -> GenericError {
+    Box::new(MismatchedDataSize { key, expected, actual })
+  }
+}
+impl Error for MismatchedDataSize {}
+unsafe impl Send for MismatchedDataSize {}
+unsafe impl Sync for MismatchedDataSize {}
+
+
+#[derive(Display, Debug, Clone)]
+#[display(fmt = "Corrupt delta log at {}, {}", url, reason)]
+pub struct CorruptDeltaLog {
+  url: String,
+  reason: String,
+}
+impl CorruptDeltaLog {
+  pub fn boxed(url: String, reason: &str) -> GenericError {
+    Box::new(CorruptDeltaLog { url, reason: reason.to_string() })
+  }
+}
+impl Error for CorruptDeltaLog {}
+unsafe impl Send for CorruptDeltaLog {}
+unsafe impl Sync for CorruptDeltaLog {}
+
+
 /* Index */
 
 #[derive(Debug, Clone)]
@@ -1,4 +1,7 @@
+use memmap2::Mmap;
 use serde::{Serialize, Deserialize};
+use serde::ser::SerializeStruct;
+use std::ops::Deref;
 use std::ops::Index;
 use std::slice::Chunks;
 use std::sync::Arc;
@@ -10,44 +13,96 @@ use std::sync::Arc;
  *   SharedByteView: shared immutable possibly-non-contiguous byte slice
  */
 
-#[derive(Serialize, Deserialize)]
+// backing memory, either owned by the process or a read-only file mapping
+#[derive(Clone)]
+enum ByteBuffer {
+  Heap(Arc<Vec<u8>>),
+  Mapped(Arc<Mmap>),
+}
+
+impl Deref for ByteBuffer {
+  type Target = [u8];
+
+  fn deref(&self) -> &Self::Target {
+    match self {
+      ByteBuffer::Heap(buffer) => buffer,
+      ByteBuffer::Mapped(mmap) => mmap,
+    }
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(from = "HeapBytes")]
 pub struct SharedBytes {
-  buffer: Arc<Vec<u8>>,
+  buffer: ByteBuffer,
+  offset: usize,
+  length: usize,
+}
+
+// serialized form, same as a plain vector
+#[derive(Deserialize)]
+struct HeapBytes {
+  buffer: Vec<u8>,
+}
+
+impl From<HeapBytes> for SharedBytes {
+  fn from(heap_bytes: HeapBytes) -> Self {
+    SharedBytes::from(heap_bytes.buffer)
+  }
+}
+
+impl Serialize for SharedBytes {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("SharedBytes", 1)?;
+    state.serialize_field("buffer", &self[..])?;
+    state.end()
+  }
 }
 
 impl SharedBytes {
+  // view into the mapping without copying, pages are faulted in on access
+  pub fn mapped(mmap: &Arc<Mmap>, offset: usize, length: usize) -> SharedBytes {
+    assert!(offset + length <= mmap.len());
+    SharedBytes { buffer: ByteBuffer::Mapped(Arc::clone(mmap)), offset, length }
+  }
+
   pub fn len(&self) -> usize {
-    self.buffer.len()
+    self.length
   }
 
   pub fn is_empty(&self) -> bool {
-    self.buffer.is_empty()
+    self.length == 0
+  }
+
+  pub fn is_mapped(&self) -> bool {
+    matches!(self.buffer, ByteBuffer::Mapped(_))
   }
 
   pub fn chunks(&self, chunk_size: usize) -> Chunks<'_, u8> {
-    self.buffer.chunks(chunk_size)
+    self.bytes().chunks(chunk_size)
   }
 
   pub fn slice(&self, offset: usize, length: usize) -> SharedByteSlice {
+    assert!(offset + length <= self.length);
     SharedByteSlice {
-      buffer: Arc::clone(&self.buffer),
-      offset,
+      buffer: self.buffer.clone(),
+      offset: self.offset + offset,
       length,
     }
   }
 
   pub fn slice_all(&self) -> SharedByteSlice {
-    SharedByteSlice {
-      buffer: Arc::clone(&self.buffer),
-      offset: 0,
-      length: self.len(),
-    }
+    self.slice(0, self.length)
+  }
+
+  fn bytes(&self) -> &[u8] {
+    &self.buffer[self.offset .. self.offset + self.length]
   }
 }
 
 impl Clone for SharedBytes {
   fn clone(&self) -> Self {
-    SharedBytes { buffer: Arc::clone(&self.buffer) }
+    SharedBytes { buffer: self.buffer.clone(), offset: self.offset, length: self.length }
   }
 }
 
@@ -55,19 +110,20 @@ impl<Idx: std::slice::SliceIndex<[u8]>> Index<Idx> for SharedBytes {
   type Output = Idx::Output;
 
   fn index(&self, index: Idx) -> &Self::Output {
-    &self.buffer[index]
+    &self.bytes()[index]
   }
 }
 
 impl From<Arc<Vec<u8>>> for SharedBytes {
   fn from(buffer: Arc<Vec<u8>>) -> Self {
-    SharedBytes { buffer }
+    let length = buffer.len();
+    SharedBytes { buffer: ByteBuffer::Heap(buffer), offset: 0, length }
   }
 }
 
 impl From<Vec<u8>> for SharedBytes {
   fn from(buffer: Vec<u8>) -> Self {
-    SharedBytes { buffer: Arc::new(buffer) }
+    SharedBytes::from(Arc::new(buffer))
   }
 }
 
@@ -76,7 +132,7 @@ impl From<Vec<u8>> for SharedBytes {
 
 #[derive(Clone)]
 pub struct SharedByteSlice {
-  buffer: Arc<Vec<u8>>,
+  buffer: ByteBuffer,
   offset: usize,
   length: usize,
 }
@@ -93,7 +149,7 @@ impl SharedByteSlice {
   pub fn slice(&self, offset: usize, length: usize) -> SharedByteSlice {
     assert!(offset + length <= self.length);
     SharedByteSlice {
-      buffer: Arc::clone(&self.buffer),
+      buffer: self.buffer.clone(),
       offset: self.offset + offset,
       length,
     }
@@ -0,0 +1,321 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::sync::Arc;
+use url::Url;
+
+use crate::common::error::CorruptDeltaLog;
+use crate::common::error::GResult;
+use crate::common::error::IncompleteDataStoreFromMeta;
+use crate::io::internal::ExternalStorage;
+use crate::meta::Context;
+use crate::store::key_position::KeyT;
+
+
+/* Log format */
+
+const PUT_OP: u8 = 0;
+const DELETE_OP: u8 = 1;
+const KEY_LENGTH: usize = std::mem::size_of::<KeyT>();
+const VALUE_LENGTH_LENGTH: usize = std::mem::size_of::<u32>();
+const MANIFEST_NAME: &str = "manifest";
+
+// op, key, then value length and value for puts
+fn write_op(buffer: &mut Vec<u8>, key: KeyT, value: Option<&[u8]>) {
+  match value {
+    Some(value) => {
+      buffer.push(PUT_OP);
+      buffer.extend_from_slice(&key.to_le_bytes());
+      buffer.extend_from_slice(&(value.len() as u32).to_le_bytes());
+      buffer.extend_from_slice(value);
+    },
+    None => {
+      buffer.push(DELETE_OP);
+      buffer.extend_from_slice(&key.to_le_bytes());
+    },
+  }
+}
+
+fn read_ops(url: &Url, buffer: &[u8]) -> GResult<Vec<(KeyT, Option<Vec<u8>>)>> {
+  let truncated = || CorruptDeltaLog::boxed(url.to_string(), "truncated operation");
+  let mut ops = Vec::new();
+  let mut cursor = 0;
+  while cursor < buffer.len() {
+    let op = buffer[cursor];
+    let key_bytes = buffer.get(cursor + 1 .. cursor + 1 + KEY_LENGTH).ok_or_else(truncated)?;
+    let key = KeyT::from_le_bytes(key_bytes.try_into()?);
+    cursor += 1 + KEY_LENGTH;
+    match op {
+      PUT_OP => {
+        let length_bytes = buffer.get(cursor .. cursor + VALUE_LENGTH_LENGTH).ok_or_else(truncated)?;
+        let value_length = u32::from_le_bytes(length_bytes.try_into()?) as usize;
+        cursor += VALUE_LENGTH_LENGTH;
+        let value = buffer.get(cursor .. cursor + value_length).ok_or_else(truncated)?;
+        cursor += value_length;
+        ops.push((key, Some(value.to_vec())));
+      },
+      DELETE_OP => ops.push((key, None)),
+      _ => return Err(CorruptDeltaLog::boxed(url.to_string(), "unknown operation")),
+    }
+  }
+  Ok(ops)
+}
+
+
+/* Log of buffered operations, one segment per sync */
+
+// the manifest holds the number of synced segments, segments beyond it are ignored
+
+#[derive(Debug)]
+pub struct DeltaLog {
+  storage: Arc<ExternalStorage>,
+  prefix_url: Url,
+  num_segments: usize,
+  pending: Vec<u8>,  // operations since the last sync
+}
+
+impl DeltaLog {
+  // prefix_url should be a directory url ending with /
+  pub fn new(storage: &Arc<ExternalStorage>, prefix_url: Url) -> DeltaLog {
+    DeltaLog { storage: Arc::clone(storage), prefix_url, num_segments: 0, pending: Vec::new() }
+  }
+
+  fn segment_url(&self, segment_idx: usize) -> GResult<Url> {
+    Ok(self.prefix_url.join(&format!("delta_{:08}", segment_idx))?)
+  }
+
+  fn write_manifest(&self) -> GResult<()> {
+    let manifest_url = self.prefix_url.join(MANIFEST_NAME)?;
+    self.storage.write_all(&manifest_url, &(self.num_segments as u64).to_le_bytes())
+  }
+
+  fn read_manifest(storage: &ExternalStorage, prefix_url: &Url) -> GResult<usize> {
+    let manifest_url = prefix_url.join(MANIFEST_NAME)?;
+    let manifest_bytes = storage.read_all(&manifest_url)?;
+    let num_segments_bytes = manifest_bytes[..].try_into()
+      .map_err(|_| CorruptDeltaLog::boxed(manifest_url.to_string(), "malformed manifest"))?;
+    Ok(u64::from_le_bytes(num_segments_bytes) as usize)
+  }
+
+  fn append(&mut self, key: KeyT, value: Option<&[u8]>) {
+    write_op(&mut self.pending, key, value)
+  }
+
+  pub fn sync(&mut self) -> GResult<()> {
+    if !self.pending.is_empty() {
+      self.storage.write_all(&self.segment_url(self.num_segments)?, &self.pending)?;
+      self.num_segments += 1;
+      self.write_manifest()?;
+      self.pending.clear();
+    }
+    Ok(())
+  }
+
+  fn replay(&self) -> GResult<Vec<(KeyT, Option<Vec<u8>>)>> {
+    let mut ops = Vec::new();
+    for segment_idx in 0..self.num_segments {
+      let segment_url = self.segment_url(segment_idx)?;
+      ops.extend(read_ops(&segment_url, &self.storage.read_all(&segment_url)?[..])?);
+    }
+    Ok(ops)
+  }
+
+  // manifest first, so that a partial removal never replays stale segments
+  fn truncate(&mut self) -> GResult<()> {
+    let num_segments = self.num_segments;
+    self.num_segments = 0;
+    self.pending.clear();
+    self.write_manifest()?;
+    for segment_idx in 0..num_segments {
+      self.storage.remove(&self.segment_url(segment_idx)?)?;
+    }
+    Ok(())
+  }
+}
+
+
+/* Sorted buffer of inserts and deletes over a static store */
+
+#[derive(Debug, Default)]
+pub struct DeltaBuffer {
+  entries: BTreeMap<KeyT, Option<Vec<u8>>>,  // None marks a deleted key
+  log: Option<DeltaLog>,
+}
+
+impl DeltaBuffer {
+  pub fn new() -> DeltaBuffer {
+    DeltaBuffer::default()
+  }
+
+  pub fn with_log(log: DeltaLog) -> DeltaBuffer {
+    DeltaBuffer { entries: BTreeMap::new(), log: Some(log) }
+  }
+
+  pub fn put(&mut self, key: KeyT, value: Vec<u8>) {
+    if let Some(log) = &mut self.log {
+      log.append(key, Some(&value));
+    }
+    self.entries.insert(key, Some(value));
+  }
+
+  pub fn delete(&mut self, key: KeyT) {
+    if let Some(log) = &mut self.log {
+      log.append(key, None);
+    }
+    self.entries.insert(key, None);
+  }
+
+  // Some(None) if the key was deleted, None if the buffer has no say
+  pub fn get(&self, key: KeyT) -> Option<Option<&[u8]>> {
+    self.entries.get(&key).map(|value| value.as_deref())
+  }
+
+  // entries with lo <= key <= hi, empty if lo > hi
+  pub fn range(&self, lo: KeyT, hi: KeyT) -> btree_map::Range<'_, KeyT, Option<Vec<u8>>> {
+    match lo <= hi {
+      true => self.entries.range(lo..=hi),
+      false => self.entries.range(lo..lo),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  // persist operations since the last sync, no-op without log
+  pub fn sync(&mut self) -> GResult<()> {
+    match &mut self.log {
+      Some(log) => log.sync(),
+      None => Ok(()),
+    }
+  }
+
+  // drop all entries, e.g. after merging them into the static store
+  pub fn clear(&mut self) -> GResult<()> {
+    self.entries.clear();
+    match &mut self.log {
+      Some(log) => log.truncate(),
+      None => Ok(()),
+    }
+  }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct DeltaBufferMeta {
+  log: Option<DeltaLogMeta>,
+  entries: Vec<(KeyT, Option<Vec<u8>>)>,  // only without log, replayed otherwise
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeltaLogMeta {
+  prefix_url: String,  // number of segments is read from the manifest
+}
+
+impl DeltaBuffer {  // for Metaserde
+  pub fn to_meta(mut self, ctx: &mut Context) -> GResult<DeltaBufferMeta> {
+    self.sync()?;
+    Ok(match self.log {
+      Some(log) => {
+        log.write_manifest()?;  // also when nothing was synced yet
+        ctx.put_storage(&log.storage);
+        DeltaBufferMeta {
+          log: Some(DeltaLogMeta { prefix_url: log.prefix_url.to_string() }),
+          entries: Vec::new(),
+        }
+      },
+      None => DeltaBufferMeta { log: None, entries: self.entries.into_iter().collect() },
+    })
+  }
+
+  pub fn from_meta(meta: DeltaBufferMeta, ctx: &Context) -> GResult<DeltaBuffer> {
+    let log_meta = match meta.log {
+      Some(log_meta) => log_meta,
+      None => return Ok(DeltaBuffer { entries: meta.entries.into_iter().collect(), log: None }),
+    };
+    let storage = ctx.storage.as_ref().ok_or_else(|| IncompleteDataStoreFromMeta::boxed("DeltaLog requires storage"))?;
+    let mut log = DeltaLog::new(storage, Url::parse(&log_meta.prefix_url)?);
+    log.num_segments = DeltaLog::read_manifest(storage, &log.prefix_url)?;
+    let entries = log.replay()?.into_iter().collect();  // later operations overwrite earlier ones
+    Ok(DeltaBuffer { entries, log: Some(log) })
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::io::storage::MemoryAdaptor;
+
+  #[test]
+  fn log_replay_ok() -> GResult<()> {
+    let es = Arc::new(ExternalStorage::new().with("mem".to_string(), Box::new(MemoryAdaptor::new()))?);
+    let log_url = Url::parse("mem:///delta/")?;
+    let mut delta = DeltaBuffer::with_log(DeltaLog::new(&es, log_url.clone()));
+    delta.put(3, vec![3; 3]);
+    delta.put(1, vec![]);
+    delta.sync()?;
+    delta.put(3, vec![4; 4]);
+    delta.delete(1);
+    delta.delete(2);
+
+    let mut ctx = Context::new();
+    let meta_bytes = crate::meta::serialize(&delta.to_meta(&mut ctx)?)?;
+    let mut delta = DeltaBuffer::from_meta(crate::meta::deserialize(&meta_bytes)?, &ctx)?;
+    assert_eq!(delta.len(), 3);
+    assert_eq!(delta.get(1), Some(None));
+    assert_eq!(delta.get(2), Some(None));
+    assert_eq!(delta.get(3), Some(Some(&[4u8; 4][..])));
+    assert_eq!(delta.get(4), None);
+
+    // clearing removes segments
+    delta.clear()?;
+    assert!(es.read_all(&log_url.join("delta_00000000")?).is_err());
+    assert!(delta.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn synced_after_meta_ok() -> GResult<()> {
+    let es = Arc::new(ExternalStorage::new().with("mem".to_string(), Box::new(MemoryAdaptor::new()))?);
+    let mut delta = DeltaBuffer::with_log(DeltaLog::new(&es, Url::parse("mem:///delta/")?));
+    delta.put(1, vec![1]);
+    let mut ctx = Context::new();
+    let meta_bytes = crate::meta::serialize(&delta.to_meta(&mut ctx)?)?;
+
+    // syncs after the meta was taken are found through the manifest
+    let mut delta = DeltaBuffer::from_meta(crate::meta::deserialize(&meta_bytes)?, &ctx)?;
+    delta.put(2, vec![2]);
+    delta.sync()?;
+    delta.delete(1);
+    delta.sync()?;
+    drop(delta);
+    let mut delta = DeltaBuffer::from_meta(crate::meta::deserialize(&meta_bytes)?, &ctx)?;
+    assert_eq!(delta.len(), 2);
+    assert_eq!(delta.get(1), Some(None));
+    assert_eq!(delta.get(2), Some(Some(&[2u8][..])));
+
+    // cleared log stays empty even if segments linger
+    delta.clear()?;
+    es.write_all(&Url::parse("mem:///delta/delta_00000000")?, &[DELETE_OP, 3, 0, 0, 0, 0, 0, 0, 0])?;
+    let delta = DeltaBuffer::from_meta(crate::meta::deserialize(&meta_bytes)?, &ctx)?;
+    assert!(delta.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn corrupt_log_err() -> GResult<()> {
+    let url = Url::parse("mem:///delta/delta_00000000")?;
+    let mut buffer = Vec::new();
+    write_op(&mut buffer, 7, Some(&[1, 2, 3]));
+    assert_eq!(read_ops(&url, &buffer)?, vec![(7, Some(vec![1, 2, 3]))]);
+    assert!(read_ops(&url, &buffer[..buffer.len() - 1]).is_err());
+    assert!(read_ops(&url, &[9]).is_err());
+    Ok(())
+  }
+}
@@ -11,7 +11,9 @@ use std::fs::OpenOptions;
 use std::hash::Hash;
 use std::hash::Hasher;
 use std::io::Write;
+use std::ops::RangeInclusive;
 use std::str::from_utf8;
+use std::sync::OnceLock;
 use zipf::ZipfDistribution;
 
 use crate::common::error::GResult;
@@ -23,6 +25,7 @@ use crate::model::load::LoadDistribution;
 use crate::store::array_store::ArrayStore;
 use crate::store::array_store::ArrayStoreState;
 use crate::store::key_position::KeyPositionCollection;
+use crate::store::key_position::KeyPositionRange;
 use crate::store::key_position::KeyT;
 
 
@@ -49,12 +52,21 @@ fn shuffle_idx(t: usize, n: usize) -> usize {
 pub struct SOSDRankDB {
   array_store: ArrayStore,
   index: Option<Box<dyn Index>>,
+  edge_keys: OnceLock<Option<(KeyT, KeyT)>>,  // first and last keys, fetched once
 }
 
 impl SOSDRankDB {
 
   pub fn new(array_store: ArrayStore) -> SOSDRankDB {
-    SOSDRankDB { array_store, index: None }
+    SOSDRankDB { array_store, index: None, edge_keys: OnceLock::new() }
+  }
+
+  pub fn len(&self) -> usize {
+    self.array_store.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.array_store.is_empty()
   }
 
   pub fn build_index(&mut self, index_builder: Box<dyn IndexBuilder>) -> GResult<()> {
@@ -85,6 +97,163 @@ impl SOSDRankDB {
     }
   }
 
+  // ranks in the order of given keys, nearby keys share reads of index layers and data
+  pub fn rank_of_many(&self, keys: &[KeyT]) -> GResult<Vec<Option<KeyRank>>> {
+    let mut key_idxs: Vec<usize> = (0..keys.len()).collect();
+    key_idxs.sort_by_key(|idx| keys[*idx]);
+    let sorted_keys: Vec<KeyT> = key_idxs.iter().map(|idx| keys[*idx]).collect();
+    let krs = self.index
+      .as_ref()
+      .expect("Index missing, trying to accessing empty data store")
+      .predict_many(&sorted_keys)?;
+
+    let mut key_ranks: Vec<Option<KeyRank>> = (0..keys.len()).map(|_| None).collect();
+    for (idxs, offset, length) in KeyPositionRange::group_overlapping(&krs) {
+      let reader = self.array_store.read_array_within(offset, length)?;
+      log::trace!("received rank buffer for {} keys in [{}, {})", idxs.len(), offset, offset + length);
+      for sorted_idx in idxs {
+        let key = sorted_keys[sorted_idx];
+        let (kb, rank) = reader.first_of_with_rank(key)?;
+        if kb.key == key {
+          key_ranks[key_idxs[sorted_idx]] = Some(KeyRank { key, rank });
+        }
+      }
+    }
+    Ok(key_ranks)
+  }
+
+  // first entry with key at or after the given key
+  pub fn lower_bound(&self, key: KeyT) -> GResult<Option<KeyRank>> {
+    let rank = self.partition_rank(key, |other_key| other_key < key)?;
+    self.key_rank_at(rank)
+  }
+
+  // last entry with key at or before the given key, e.g. latest event at time t
+  pub fn predecessor(&self, key: KeyT) -> GResult<Option<KeyRank>> {
+    match self.partition_rank(key, |other_key| other_key <= key)? {
+      0 => Ok(None),
+      end_rank => {
+        // rank of the first duplicate, usually in the same pages
+        let predecessor_key = self.key_rank_at(end_rank - 1)?.expect("Predecessor within array").key;
+        self.lower_bound(predecessor_key)
+      },
+    }
+  }
+
+  // first entry with key strictly after the given key
+  pub fn successor(&self, key: KeyT) -> GResult<Option<KeyRank>> {
+    let rank = self.partition_rank(key, |other_key| other_key <= key)?;
+    self.key_rank_at(rank)
+  }
+
+  // number of entries with lo <= key <= hi
+  pub fn count_range(&self, lo: KeyT, hi: KeyT) -> GResult<usize> {
+    if lo > hi {
+      return Ok(0);
+    }
+    let end_rank = self.partition_rank(hi, |other_key| other_key <= hi)?;
+    let start_rank = self.partition_rank(lo, |other_key| other_key < lo)?;
+    Ok(end_rank - start_rank)
+  }
+
+  // bounds on count_range from index layers only, without reading the data layer,
+  // assuming predicted ranges cover the partitions of lo and hi
+  pub fn approximate_count_range(&self, lo: KeyT, hi: KeyT) -> GResult<RangeInclusive<usize>> {
+    if lo > hi {
+      return Ok(0..=0);
+    }
+    let end_ranks = self.approximate_partition_rank(hi, |other_key| other_key <= hi)?;
+    let start_ranks = self.approximate_partition_rank(lo, |other_key| other_key < lo)?;
+    Ok(end_ranks.start().saturating_sub(*start_ranks.end())..=(end_ranks.end() - start_ranks.start()))
+  }
+
+  // entry at the rank, e.g. select(q * (len - 1)) for the q-quantile
+  pub fn select(&self, rank: usize) -> GResult<Option<KeyRank>> {
+    self.key_rank_at(rank)
+  }
+
+  fn key_rank_at(&self, rank: usize) -> GResult<Option<KeyRank>> {
+    if rank >= self.array_store.len() {
+      return Ok(None);
+    }
+    Ok(Some(KeyRank { key: self.key_at(rank)?, rank }))
+  }
+
+  fn key_at(&self, rank: usize) -> GResult<KeyT> {
+    let data_size = self.array_store.data_size();
+    Ok(self.array_store.read_array_within(rank * data_size, data_size)?.key_at(0))
+  }
+
+  // number of entries before the key's partition, starting from the predicted range then
+  // widening toward the partition when it lies beyond the predicted range
+  fn partition_rank<P: Fn(KeyT) -> bool>(&self, key: KeyT, is_before: P) -> GResult<usize> {
+    let num_elements = self.array_store.len();
+    let data_size = self.array_store.data_size();
+
+    // dataset edges, also where models may not cover the key
+    match self.edge_keys()? {
+      Some((first_key, _)) if !is_before(first_key) => return Ok(0),
+      Some((_, last_key)) if is_before(last_key) => return Ok(num_elements),
+      None => return Ok(0),
+      _ => (),
+    }
+    let kpr = self.index
+      .as_ref()
+      .expect("Index missing, trying to accessing empty data store")
+      .predict(&key)?;
+    let mut reader = self.array_store.read_array_within(kpr.offset, kpr.length)?;
+
+    // partition is within [lo_rank, hi_rank]
+    let (mut lo_rank, mut hi_rank) = (0, num_elements);
+    let mut step = std::cmp::max(reader.len(), 1);
+    loop {
+      let start_rank = reader.start_rank();
+      let end_rank = start_rank + reader.len();
+      let idx = reader.partition_point(&is_before);
+      let (next_start_rank, next_end_rank) = if idx == 0 && start_rank > lo_rank {
+        hi_rank = start_rank;
+        (std::cmp::max(lo_rank, start_rank.saturating_sub(step)), start_rank)
+      } else if idx == reader.len() && end_rank < hi_rank {
+        lo_rank = end_rank;
+        (end_rank, std::cmp::min(hi_rank, end_rank + step))
+      } else {
+        return Ok(start_rank + idx);
+      };
+      step *= 2;
+      reader = self.array_store.read_array_within(next_start_rank * data_size, (next_end_rank - next_start_rank) * data_size)?;
+    }
+  }
+
+  // ranks that the partition may take given the predicted range of the key
+  fn approximate_partition_rank<P: Fn(KeyT) -> bool>(&self, key: KeyT, is_before: P) -> GResult<RangeInclusive<usize>> {
+    let num_elements = self.array_store.len();
+    let data_size = self.array_store.data_size();
+    match self.edge_keys()? {
+      Some((first_key, _)) if !is_before(first_key) => return Ok(0..=0),
+      Some((_, last_key)) if is_before(last_key) => return Ok(num_elements..=num_elements),
+      None => return Ok(0..=0),
+      _ => (),
+    }
+    let kpr = self.index
+      .as_ref()
+      .expect("Index missing, trying to accessing empty data store")
+      .predict(&key)?;
+    let start_rank = std::cmp::min(kpr.offset / data_size, num_elements);
+    let end_rank = std::cmp::min((kpr.offset + kpr.length).div_ceil(data_size), num_elements);
+    Ok(start_rank..=end_rank)
+  }
+
+  fn edge_keys(&self) -> GResult<Option<(KeyT, KeyT)>> {
+    if let Some(edge_keys) = self.edge_keys.get() {
+      return Ok(*edge_keys);
+    }
+    let edge_keys = match self.array_store.len() {
+      0 => None,
+      num_elements => Some((self.key_at(0)?, self.key_at(num_elements - 1)?)),
+    };
+    Ok(*self.edge_keys.get_or_init(|| edge_keys))
+  }
+
   pub fn reconstruct_key_positions(&self) -> GResult<KeyPositionCollection> {
     // SOSD blob contains uint32/uint64s written next to each other
     // We can reconstruct the kps by multiplying the rank with data size
@@ -199,6 +368,7 @@ impl SOSDRankDB {  // for Metaserde
         Some(index_meta) => Some(IndexMeta::from_meta(index_meta, index_ctx)?),
         None => None,
       },
+      edge_keys: OnceLock::new(),
     })
   }
 }
@@ -209,3 +379,213 @@ pub fn read_keyset(keyset_bytes: &[u8]) -> GResult<Vec<KeyRank>> {
     KeyRank { key, rank }
   }).collect())
 }
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rayon::prelude::*;
+  use std::sync::Arc;
+  use url::Url;
+
+  use crate::index::hierarchical::BalanceStackIndexBuilder;
+  use crate::io::internal::ExternalStorage;
+  use crate::io::profile::AffineStorageProfile;
+  use crate::io::profile::Bandwidth;
+  use crate::io::profile::Latency;
+  use crate::io::storage::MemoryAdaptor;
+  use crate::model::step::StepMultipleDrafter;
+
+  // builds a stack index over keys in memory, then reloads it from metadata
+  fn build_reload_in_memory(keys: &[KeyT]) -> GResult<(SOSDRankDB, Arc<ExternalStorage>)> {
+    // sosd blob of uint64 keys, led by its length
+    let num_keys = keys.len();
+    let mut blob = vec![0u8; 8 * (num_keys + 1)];
+    LittleEndian::write_u64(&mut blob[..8], num_keys as u64);
+    LittleEndian::write_u64_into(keys, &mut blob[8..]);
+    let mema = MemoryAdaptor::new();
+    let data_url = Url::parse("mem:///data/")?;
+    let db_url = Url::parse("mem:///db/")?;
+    let mut es = ExternalStorage::new().with("mem".to_string(), Box::new(mema.clone()))?;
+    es.track_prefix(&data_url);
+    let es = Arc::new(es);
+    es.write_all(&data_url.join("keys_uint64")?, &blob)?;
+
+    // build a stack index into memory
+    let array_store = ArrayStore::from_exact(&es, data_url.clone(), "keys_uint64".to_string(), 8, 8, num_keys);
+    let mut sosd_db = SOSDRankDB::new(array_store);
+    let profile = AffineStorageProfile::new(Latency::from_micros(100), Bandwidth::from_mbps(100.0));
+    let drafter = StepMultipleDrafter::exponentiation(256, 1024, 4.0, 16).to_serial();
+    let index_builder = BalanceStackIndexBuilder::new(&es, Box::new(drafter), &profile, db_url.clone());
+    sosd_db.attach_index(index_builder.build_index(&sosd_db.reconstruct_key_positions()?)?);
+    assert!(mema.urls().iter().any(|url| url.as_str().starts_with(db_url.as_str())), "Expected index layers in memory");
+
+    // reload from metadata
+    let mut data_ctx = Context::new();
+    let mut index_ctx = Context::new();
+    let meta = sosd_db.to_meta(&mut data_ctx, &mut index_ctx)?;
+    Ok((SOSDRankDB::from_meta(meta, &data_ctx, &index_ctx)?, es))
+  }
+
+  fn expected_ranks(keys: &[KeyT]) -> Vec<KeyRank> {
+    let mut expected_rank = 0;
+    keys.iter().enumerate().map(|(rank, key)| {
+      if rank > 0 && keys[rank - 1] != *key {
+        expected_rank = rank;
+      }
+      KeyRank { key: *key, rank: expected_rank }
+    }).collect()
+  }
+
+  #[test]
+  fn build_reload_in_memory_ok() -> GResult<()> {
+    let num_keys = 2000;
+    let keys: Vec<KeyT> = (0..num_keys).map(|idx| (idx * idx / 7) as KeyT).collect();
+    let (sosd_db, _) = build_reload_in_memory(&keys)?;
+    for kr in expected_ranks(&keys) {
+      assert_eq!(sosd_db.rank_of(kr.key)?, Some(kr));
+    }
+    assert_eq!(sosd_db.rank_of(1 + keys[num_keys - 1])?, None);
+    Ok(())
+  }
+
+  #[test]
+  fn concurrent_rank_of_ok() -> GResult<()> {
+    let num_keys = 5000;
+    let keys: Vec<KeyT> = (0..num_keys).map(|idx| (idx * idx / 3) as KeyT).collect();
+    let (sosd_db, _) = build_reload_in_memory(&keys)?;
+
+    // one reloaded db shared by all workers
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build()?;
+    let expected = expected_ranks(&keys);
+    let mismatches = pool.install(|| {
+      expected.par_iter()
+        .filter(|kr| !matches!(sosd_db.rank_of(kr.key), Ok(Some(rcv_kr)) if rcv_kr == **kr))
+        .count()
+    });
+    assert_eq!(mismatches, 0);
+    Ok(())
+  }
+
+  #[test]
+  fn rank_of_many_ok() -> GResult<()> {
+    let num_keys = 5000;
+    let keys: Vec<KeyT> = (0..num_keys).map(|idx| (idx * idx / 5) as KeyT).collect();
+    let (sosd_db, es) = build_reload_in_memory(&keys)?;
+
+    // unsorted queries with duplicates and missing keys
+    let queries: Vec<KeyT> = keys.iter().rev().flat_map(|key| [*key, *key + 1]).collect();
+    es.reset_stats();
+    let batched_ranks = sosd_db.rank_of_many(&queries)?;
+    let batched_requests = es.stats().total.hits + es.stats().total.misses;
+    es.reset_stats();
+    for (query, batched_rank) in queries.iter().zip(batched_ranks.iter()) {
+      assert_eq!(sosd_db.rank_of(*query)?, *batched_rank, "Mismatched rank of {}", query);
+    }
+    let single_requests = es.stats().total.hits + es.stats().total.misses;
+    assert!(batched_requests * 10 < single_requests, "Expected shared reads, {} vs {}", batched_requests, single_requests);
+    assert!(batched_ranks.iter().step_by(2).all(|kr| kr.is_some()), "Expected all existing keys found");
+    Ok(())
+  }
+
+  // predicts only the last entry at or before the key, so neighbors lie outside
+  #[derive(Debug)]
+  struct NarrowIndex {
+    keys: Vec<KeyT>,
+  }
+
+  impl Index for NarrowIndex {
+    fn predict(&self, key: &KeyT) -> GResult<KeyPositionRange> {
+      let rank = self.keys.partition_point(|other_key| other_key <= key).saturating_sub(1);
+      Ok(KeyPositionRange::from_bound(*key, *key, rank * 8, (rank + 1) * 8))
+    }
+
+    fn get_load(&self) -> Vec<LoadDistribution> {
+      vec![LoadDistribution::exact(8)]
+    }
+  }
+
+  impl crate::index::IndexMetaserde for NarrowIndex {
+    fn to_meta(&self, _ctx: &mut Context) -> GResult<IndexMeta> {
+      Err("NarrowIndex is for tests only".into())
+    }
+  }
+
+  fn assert_neighbor_queries(sosd_db: &SOSDRankDB, keys: &[KeyT]) -> GResult<()> {
+    let key_rank_at = |rank: usize| (rank < keys.len()).then(|| KeyRank { key: keys[rank], rank });
+    let max_key = keys[keys.len() - 1];
+
+    // around every key, and beyond dataset edges
+    let queries = keys.iter().flat_map(|key| [*key - 1, *key, *key + 1]).chain([0, 99, max_key + 1, KeyT::MAX]);
+    for query in queries {
+      let lower_bound_rank = keys.partition_point(|key| *key < query);
+      let successor_rank = keys.partition_point(|key| *key <= query);
+      assert_eq!(sosd_db.lower_bound(query)?, key_rank_at(lower_bound_rank), "Mismatched lower bound of {}", query);
+      assert_eq!(sosd_db.successor(query)?, key_rank_at(successor_rank), "Mismatched successor of {}", query);
+      let expected_predecessor = match successor_rank {
+        0 => None,
+        rank => key_rank_at(keys.partition_point(|key| *key < keys[rank - 1])),
+      };
+      assert_eq!(sosd_db.predecessor(query)?, expected_predecessor, "Mismatched predecessor of {}", query);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn neighbor_queries_ok() -> GResult<()> {
+    let num_keys = 3000;
+    let keys: Vec<KeyT> = (0..num_keys).map(|idx| (100 + idx * idx / 5) as KeyT).collect();
+    let (mut sosd_db, _) = build_reload_in_memory(&keys)?;
+    assert_neighbor_queries(&sosd_db, &keys)?;
+
+    // neighbors and duplicates beyond predicted ranges
+    sosd_db.attach_index(Box::new(NarrowIndex { keys: keys.clone() }));
+    assert_neighbor_queries(&sosd_db, &keys)
+  }
+
+  #[test]
+  fn count_range_ok() -> GResult<()> {
+    let num_keys = 3000;
+    let keys: Vec<KeyT> = (0..num_keys).map(|idx| (100 + idx * idx / 5) as KeyT).collect();
+    let (sosd_db, es) = build_reload_in_memory(&keys)?;
+    let max_key = keys[num_keys - 1];
+    let data_prefix = "mem:///data/";
+    es.reset_stats();
+    assert_eq!(sosd_db.count_range(1000, max_key / 2)?, keys.iter().filter(|key| 1000 <= **key && **key <= max_key / 2).count());
+    let data_counters = &es.stats().by_prefix[data_prefix];
+    assert!(data_counters.hits + data_counters.misses > 0, "Expected exact counts to read the data layer");
+
+    let bounds = [0, 99, 100, 101, 150, 1000, 1001, max_key / 3, max_key / 2, max_key - 1, max_key, max_key + 1, KeyT::MAX];
+    for lo in bounds {
+      for hi in bounds {
+        let expected = keys.iter().filter(|key| lo <= **key && **key <= hi).count();
+        assert_eq!(sosd_db.count_range(lo, hi)?, expected, "Mismatched count in [{}, {}]", lo, hi);
+
+        // index layers only, edge keys are fetched by now
+        es.reset_stats();
+        let approximate = sosd_db.approximate_count_range(lo, hi)?;
+        assert!(approximate.contains(&expected), "Expected {} in {:?} for [{}, {}]", expected, approximate, lo, hi);
+        let data_counters = &es.stats().by_prefix[data_prefix];
+        assert_eq!(data_counters.hits + data_counters.misses, 0, "Expected no data reads for [{}, {}]", lo, hi);
+      }
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn select_ok() -> GResult<()> {
+    let num_keys = 2000;
+    let keys: Vec<KeyT> = (0..num_keys).map(|idx| (idx * idx / 7) as KeyT).collect();
+    let (sosd_db, _) = build_reload_in_memory(&keys)?;
+    assert_eq!(sosd_db.len(), num_keys);
+    for (rank, key) in keys.iter().enumerate() {
+      assert_eq!(sosd_db.select(rank)?, Some(KeyRank { key: *key, rank }));
+    }
+    assert_eq!(sosd_db.select(num_keys)?, None);
+
+    // median back to its key
+    let median = sosd_db.select((num_keys - 1) / 2)?.expect("Median within array");
+    assert_eq!(sosd_db.count_range(0, median.key)?, sosd_db.successor(median.key)?.expect("Successor of median").rank);
+    Ok(())
+  }
+}
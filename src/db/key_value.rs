@@ -0,0 +1,712 @@
+use serde::{Serialize, Deserialize};
+use std::collections::btree_map;
+use std::iter::Peekable;
+use std::sync::Arc;
+use url::Url;
+
+use crate::common::error::GResult;
+use crate::common::error::MismatchedDataSize;
+use crate::common::error::OutofCoverageError;
+use crate::common::error::UnsortedKeyBuffers;
+use crate::db::delta::DeltaBuffer;
+use crate::db::delta::DeltaBufferMeta;
+use crate::db::delta::DeltaLog;
+use crate::index::Index;
+use crate::index::IndexBuilder;
+use crate::index::IndexMeta;
+use crate::io::internal::ExternalStorage;
+use crate::meta::Context;
+use crate::store::DataStore;
+use crate::store::DataStoreMeta;
+use crate::store::DataStoreScanner;
+use crate::store::Readahead;
+use crate::store::key_buffer::KeyBuffer;
+use crate::store::key_position::KEY_LENGTH;
+use crate::store::key_position::KeyPositionCollection;
+use crate::store::key_position::KeyPositionRange;
+use crate::store::key_position::KeyT;
+use crate::store::store_designer::StoreDesigner;
+
+const SCAN_MIN_READAHEAD: usize = 1 << 12;  // 4 KB
+const SCAN_MAX_READAHEAD: usize = 1 << 20;  // 1 MB
+
+
+/* DB that stores values by key and serves point lookups */
+
+#[derive(Debug)]
+pub struct KeyValueDB {
+  data_store: Box<dyn DataStore>,
+  index: Option<Box<dyn Index>>,
+  appended_runs: Vec<(KeyT, Box<dyn Index>)>,  // indexes of entries appended by compaction, by first key
+  min_key: Option<KeyT>,  // models only cover keys from the smallest written one
+  max_key: Option<KeyT>,
+  delta: DeltaBuffer,  // updates since the last write or compaction
+}
+
+impl KeyValueDB {
+  pub fn new(data_store: Box<dyn DataStore>) -> KeyValueDB {
+    KeyValueDB {
+      data_store,
+      index: None,
+      appended_runs: Vec::new(),
+      min_key: None,
+      max_key: None,
+      delta: DeltaBuffer::new(),
+    }
+  }
+
+  // persist updates to the log before compaction, replaces buffered updates
+  pub fn with_delta_log(mut self, log: DeltaLog) -> KeyValueDB {
+    self.delta = DeltaBuffer::with_log(log);
+    self
+  }
+
+  // ArrayStore if all values have the same size, BlockStore otherwise
+  pub fn new_designed(storage: &Arc<ExternalStorage>, prefix_url: Url, store_name: String, key_buffers: &[KeyBuffer]) -> KeyValueDB {
+    KeyValueDB::new(StoreDesigner::new(storage).design_for_kbs(key_buffers, prefix_url, store_name))
+  }
+
+  // replace all entries, keys must be strictly increasing, drops the current index and buffered updates
+  pub fn write(&mut self, key_buffers: &[KeyBuffer]) -> GResult<KeyPositionCollection> {
+    let kps = self.rewrite(key_buffers)?;
+    self.delta.clear()?;
+    Ok(kps)
+  }
+
+  // replace all entries in the data store, keeping buffered updates
+  fn rewrite(&mut self, key_buffers: &[KeyBuffer]) -> GResult<KeyPositionCollection> {
+    for kb_pair in key_buffers.windows(2) {
+      if kb_pair[0].key >= kb_pair[1].key {
+        return Err(UnsortedKeyBuffers::boxed(kb_pair[0].key, kb_pair[1].key));
+      }
+    }
+    let mut data_writer = self.data_store.begin_write()?;
+    for kb in key_buffers {
+      data_writer.write(kb)?;
+    }
+    let kps = data_writer.commit()?;
+    self.index = None;
+    // run_N_layer_* blobs of dropped runs stay in storage, overwritten once run N is appended again
+    self.appended_runs.clear();
+    self.min_key = key_buffers.first().map(|kb| kb.key);
+    self.max_key = key_buffers.last().map(|kb| kb.key);
+    Ok(kps)
+  }
+
+  pub fn build_index(&mut self, kps: &KeyPositionCollection, index_builder: &dyn IndexBuilder) -> GResult<()> {
+    self.attach_index(index_builder.build_index(kps)?);
+    Ok(())
+  }
+
+  pub fn attach_index(&mut self, index: Box<dyn Index>) {
+    self.index = Some(index)
+  }
+
+  // buffered until compaction, visible to reads right away, values must fit the data store
+  pub fn put(&mut self, key: KeyT, value: Vec<u8>) -> GResult<()> {
+    if let Some(data_size) = self.data_store.fixed_data_size() {
+      if KEY_LENGTH + value.len() != data_size {
+        return Err(MismatchedDataSize::boxed(key, data_size, KEY_LENGTH + value.len()));
+      }
+    }
+    self.delta.put(key, value);
+    Ok(())
+  }
+
+  pub fn delete(&mut self, key: KeyT) {
+    self.delta.delete(key)
+  }
+
+  // persist buffered updates to the delta log if any
+  pub fn sync(&mut self) -> GResult<()> {
+    self.delta.sync()
+  }
+
+  // merge buffered updates into the data store, then index them with the builder; updates
+  // past the largest stored key are appended and indexed as a run, others rewrite everything
+  pub fn compact(&mut self, index_builder: &dyn IndexBuilder) -> GResult<()> {
+    if self.delta.is_empty() && self.index.is_some() {
+      return Ok(());
+    }
+    match self.max_key {
+      Some(max_key) if self.index.is_some() && self.delta.range(0, max_key).next().is_none() => {
+        self.compact_append(index_builder)
+      },
+      _ => self.compact_rewrite(index_builder),
+    }
+  }
+
+  // buffered updates are all after the stored keys, deletes among them are no-ops
+  fn compact_append(&mut self, index_builder: &dyn IndexBuilder) -> GResult<()> {
+    let key_buffers: Vec<KeyBuffer> = self.delta.range(0, KeyT::MAX)
+      .filter_map(|(key, value)| value.as_ref().map(|value| KeyBuffer::new(*key, value.clone())))
+      .collect();
+    log::info!("Compacting {} buffered updates by appending {} entries", self.delta.len(), key_buffers.len());
+    if let (Some(first_kb), Some(last_kb)) = (key_buffers.first(), key_buffers.last()) {
+      let mut data_writer = self.data_store.begin_append()?;
+      for kb in &key_buffers {
+        data_writer.write(kb)?;
+      }
+      let kps = data_writer.commit()?;
+      // appended entries are stored, a later compaction must not append them again
+      self.max_key = Some(last_kb.key);
+      // numbered from 1 after each rewrite, reusing blob names of runs it dropped
+      let run_name = format!("run_{}_", self.appended_runs.len() + 1);
+      let run_index = index_builder.build_index_named(&kps, &run_name)?;
+      self.appended_runs.push((first_kb.key, run_index));
+    }
+    self.delta.clear()
+  }
+
+  fn compact_rewrite(&mut self, index_builder: &dyn IndexBuilder) -> GResult<()> {
+    let key_buffers = self.scan(0, KeyT::MAX)?.collect::<GResult<Vec<KeyBuffer>>>()?;
+    log::info!("Compacting {} buffered updates by rewriting {} entries", self.delta.len(), key_buffers.len());
+    let kps = self.rewrite(&key_buffers)?;
+    if !key_buffers.is_empty() {
+      self.build_index(&kps, index_builder)?;
+    }
+    self.delta.clear()
+  }
+
+  // the run holding the key if appended by compaction, the main index otherwise
+  fn predict(&self, key: KeyT) -> GResult<KeyPositionRange> {
+    let run_idx = self.appended_runs.partition_point(|(first_key, _)| *first_key <= key);
+    match run_idx {
+      0 => self.index
+        .as_ref()
+        .expect("Index missing, trying to accessing empty data store")
+        .predict(&key),
+      _ => self.appended_runs[run_idx - 1].1.predict(&key),
+    }
+  }
+
+  pub fn get(&self, key: KeyT) -> GResult<Option<Vec<u8>>> {
+    if let Some(value) = self.delta.get(key) {
+      return Ok(value.map(|value| value.to_vec()));
+    }
+    if self.min_key.is_none_or(|min_key| key < min_key) {
+      return Ok(None);
+    }
+    let kpr = self.predict(key)?;
+    let reader = self.data_store.read_within(kpr.offset, kpr.length)?;
+    match reader.first_of(key) {
+      Ok(kb) if kb.key == key => Ok(Some(kb.buffer[..].to_vec())),
+      Ok(_) => Ok(None),  // no entry with matching key
+      Err(e) if e.is::<OutofCoverageError>() => Ok(None),
+      Err(e) => Err(e),
+    }
+  }
+
+  // entries with lo <= key <= hi in key order, starting at the predicted position of lo,
+  // merged with buffered updates
+  pub fn scan(&self, lo: KeyT, hi: KeyT) -> GResult<KeyValueScanner<'_>> {
+    let delta_entries = self.delta.range(lo, hi).peekable();
+    if self.min_key.is_none() || lo > hi {
+      let scanner = self.data_store.scan_from(0, Readahead::new(SCAN_MIN_READAHEAD, SCAN_MAX_READAHEAD));
+      return Ok(KeyValueScanner { scanner, delta_entries, static_kb: None, lo, hi, is_done: true });
+    }
+    let (mut offset, mut step) = match self.min_key {
+      Some(min_key) if lo > min_key => {
+        let kpr = self.predict(lo)?;
+        (kpr.offset, std::cmp::max(kpr.length, 1))
+      },
+      _ => (0, 0),
+    };
+
+    // the predicted range may start after lo, step back until the first entry is not after lo
+    loop {
+      let readahead = Readahead::new(std::cmp::max(step, SCAN_MIN_READAHEAD), SCAN_MAX_READAHEAD);
+      let mut scanner = self.data_store.scan_from(offset, readahead);
+      let first_kb = scanner.next().transpose()?;
+      if offset > 0 && first_kb.as_ref().is_none_or(|kb| kb.key > lo) {
+        offset = offset.saturating_sub(step);
+        step *= 2;
+        continue;
+      }
+      return Ok(KeyValueScanner {
+        scanner,
+        delta_entries,
+        is_done: first_kb.as_ref().is_none_or(|kb| kb.key > hi),
+        static_kb: first_kb.filter(|kb| lo <= kb.key && kb.key <= hi),
+        lo,
+        hi,
+      });
+    }
+  }
+
+  // first entry with key at or after the given key
+  pub fn lower_bound(&self, key: KeyT) -> GResult<Option<KeyBuffer>> {
+    self.scan(key, KeyT::MAX)?.next().transpose()
+  }
+
+  // last entry with key at or before the given key
+  pub fn predecessor(&self, key: KeyT) -> GResult<Option<KeyBuffer>> {
+    let mut bound = key;
+    loop {
+      let static_kb = self.static_predecessor(bound)?;
+      let delta_entry = self.delta.range(0, bound).next_back();
+      match (delta_entry, static_kb) {
+        (Some((delta_key, _)), Some(kb)) if kb.key > *delta_key => return Ok(Some(kb)),
+        (Some((delta_key, Some(value))), _) => return Ok(Some(KeyBuffer::new(*delta_key, value.clone()))),
+        // deleted, look before it
+        (Some((0, None)), _) => return Ok(None),
+        (Some((delta_key, None)), _) => bound = delta_key - 1,
+        (None, static_kb) => return Ok(static_kb),
+      }
+    }
+  }
+
+  // first entry with key strictly after the given key
+  pub fn successor(&self, key: KeyT) -> GResult<Option<KeyBuffer>> {
+    match key.checked_add(1) {
+      Some(next_key) => self.lower_bound(next_key),
+      None => Ok(None),
+    }
+  }
+
+  // last stored entry at or before the key, ignoring buffered updates, starting from the
+  // predicted range then widening toward the entry when it lies beyond the predicted range
+  fn static_predecessor(&self, key: KeyT) -> GResult<Option<KeyBuffer>> {
+    let max_key = match (self.min_key, self.max_key) {
+      (Some(min_key), Some(max_key)) if min_key <= key => max_key,
+      _ => return Ok(None),
+    };
+    let kpr = self.predict(key)?;
+    let (mut start_offset, mut end_offset) = (kpr.offset, kpr.offset + kpr.length);
+    let mut step = std::cmp::max(kpr.length, 1);
+    loop {
+      let reader = self.data_store.read_within(start_offset, end_offset - start_offset)?;
+      let kbs: Vec<KeyBuffer> = reader.iter().collect();
+      let idx = kbs.partition_point(|kb| kb.key <= key);
+      if idx == 0 && start_offset > 0 {
+        start_offset = start_offset.saturating_sub(step);
+      } else if idx == kbs.len() && kbs.last().is_none_or(|kb| kb.key < max_key) {
+        end_offset += step;
+      } else if idx == 0 {
+        return Ok(None);
+      } else {
+        return Ok(kbs.into_iter().nth(idx - 1));
+      }
+      step *= 2;
+    }
+  }
+}
+
+
+/* Range scan */
+
+pub struct KeyValueScanner<'a> {
+  scanner: Box<dyn DataStoreScanner + 'a>,
+  delta_entries: Peekable<btree_map::Range<'a, KeyT, Option<Vec<u8>>>>,
+  static_kb: Option<KeyBuffer>,  // next entry from the data store, not yet merged
+  lo: KeyT,
+  hi: KeyT,
+  is_done: bool,  // data store passed hi or failed
+}
+
+impl<'a> KeyValueScanner<'a> {
+  fn next_static(&mut self) -> Option<GResult<KeyBuffer>> {
+    while !self.is_done {
+      match self.scanner.next() {
+        Some(Ok(kb)) if kb.key < self.lo => continue,
+        Some(Ok(kb)) if kb.key <= self.hi => return Some(Ok(kb)),
+        Some(Err(e)) => {
+          self.is_done = true;
+          return Some(Err(e));
+        },
+        _ => self.is_done = true,
+      }
+    }
+    None
+  }
+}
+
+impl<'a> Iterator for KeyValueScanner<'a> {
+  type Item = GResult<KeyBuffer>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      if self.static_kb.is_none() {
+        match self.next_static() {
+          Some(Ok(kb)) => self.static_kb = Some(kb),
+          Some(Err(e)) => return Some(Err(e)),
+          None => (),
+        }
+      }
+      let delta_key = match (self.delta_entries.peek(), &self.static_kb) {
+        (None, _) => return self.static_kb.take().map(Ok),
+        (Some((delta_key, _)), Some(kb)) if kb.key < **delta_key => return self.static_kb.take().map(Ok),
+        (Some((delta_key, _)), _) => **delta_key,
+      };
+
+      // buffered update overrides the stored entry, if any
+      if self.static_kb.as_ref().is_some_and(|kb| kb.key == delta_key) {
+        self.static_kb = None;
+      }
+      if let Some((_, Some(value))) = self.delta_entries.next() {
+        return Some(Ok(KeyBuffer::new(delta_key, value.clone())));
+      }
+    }
+  }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct KeyValueDBMeta {
+  data_store: DataStoreMeta,
+  index: Option<IndexMeta>,
+  appended_runs: Vec<(KeyT, IndexMeta)>,
+  min_key: Option<KeyT>,
+  max_key: Option<KeyT>,
+  delta: DeltaBufferMeta,
+}
+
+impl KeyValueDB {  // for Metaserde
+  pub fn to_meta(self, data_ctx: &mut Context, index_ctx: &mut Context) -> GResult<KeyValueDBMeta> {
+    Ok(KeyValueDBMeta {
+      data_store: self.data_store.to_meta(data_ctx)?,
+      index: match self.index {
+        Some(index) => Some(index.to_meta(index_ctx)?),
+        None => None,
+      },
+      appended_runs: self.appended_runs.iter()
+        .map(|(first_key, run_index)| Ok((*first_key, run_index.to_meta(index_ctx)?)))
+        .collect::<GResult<Vec<_>>>()?,
+      min_key: self.min_key,
+      max_key: self.max_key,
+      delta: self.delta.to_meta(data_ctx)?,
+    })
+  }
+
+  pub fn from_meta(meta: KeyValueDBMeta, data_ctx: &Context, index_ctx: &Context) -> GResult<KeyValueDB> {
+    Ok(KeyValueDB {
+      data_store: DataStoreMeta::from_meta(meta.data_store, data_ctx)?,
+      index: match meta.index {
+        Some(index_meta) => Some(IndexMeta::from_meta(index_meta, index_ctx)?),
+        None => None,
+      },
+      appended_runs: meta.appended_runs.into_iter()
+        .map(|(first_key, run_meta)| Ok((first_key, IndexMeta::from_meta(run_meta, index_ctx)?)))
+        .collect::<GResult<Vec<_>>>()?,
+      min_key: meta.min_key,
+      max_key: meta.max_key,
+      delta: DeltaBuffer::from_meta(meta.delta, data_ctx)?,
+    })
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::BTreeMap;
+
+  use crate::index::hierarchical::BalanceStackIndexBuilder;
+  use crate::io::profile::AffineStorageProfile;
+  use crate::io::profile::Bandwidth;
+  use crate::io::profile::Latency;
+  use crate::io::storage::MemoryAdaptor;
+  use crate::meta;
+  use crate::model::load::LoadDistribution;
+  use crate::model::step::StepMultipleDrafter;
+
+  // writes, indexes and reloads from serialized metadata
+  fn build_reload_in_memory(key_buffers: &[KeyBuffer]) -> GResult<KeyValueDB> {
+    let es = Arc::new(ExternalStorage::new().with("mem".to_string(), Box::new(MemoryAdaptor::new()))?);
+    let data_url = Url::parse("mem:///data/")?;
+    let db_url = Url::parse("mem:///db/")?;
+    let mut kv_db = KeyValueDB::new_designed(&es, data_url, "kv".to_string(), key_buffers);
+    let kps = kv_db.write(key_buffers)?;
+    let profile = AffineStorageProfile::new(Latency::from_micros(100), Bandwidth::from_mbps(100.0));
+    let drafter = StepMultipleDrafter::exponentiation(256, 1024, 4.0, 16).to_serial();
+    kv_db.build_index(&kps, &BalanceStackIndexBuilder::new(&es, Box::new(drafter), &profile, db_url))?;
+
+    let mut data_ctx = Context::new();
+    let mut index_ctx = Context::new();
+    let meta_bytes = meta::serialize(&kv_db.to_meta(&mut data_ctx, &mut index_ctx)?)?;
+    KeyValueDB::from_meta(meta::deserialize(&meta_bytes)?, &data_ctx, &index_ctx)
+  }
+
+  fn assert_get_all(kv_db: &KeyValueDB, key_buffers: &[KeyBuffer]) -> GResult<()> {
+    for kb in key_buffers {
+      assert_eq!(kv_db.get(kb.key)?, Some(kb.buffer[..].to_vec()), "Mismatched value of key {}", kb.key);
+      assert_eq!(kv_db.get(kb.key + 1)?, None);
+    }
+    assert_eq!(kv_db.get(0)?, None);
+    Ok(())
+  }
+
+  #[test]
+  fn variable_values_ok() -> GResult<()> {
+    let key_buffers: Vec<KeyBuffer> = (1..3000)
+      .map(|idx| KeyBuffer::new(idx * 3, vec![idx as u8; idx as usize % 37]))
+      .collect();
+    let kv_db = build_reload_in_memory(&key_buffers)?;
+    assert!(format!("{:?}", kv_db.data_store).starts_with("BlockStore"));
+    assert_get_all(&kv_db, &key_buffers)
+  }
+
+  #[test]
+  fn fixed_values_ok() -> GResult<()> {
+    let key_buffers: Vec<KeyBuffer> = (1..3000)
+      .map(|idx| KeyBuffer::new(idx * idx, (idx as u32).to_le_bytes().to_vec()))
+      .collect();
+    let kv_db = build_reload_in_memory(&key_buffers)?;
+    assert!(format!("{:?}", kv_db.data_store).starts_with("ArrayStore"));
+    assert_get_all(&kv_db, &key_buffers)
+  }
+
+  fn assert_scans(kv_db: &KeyValueDB, key_buffers: &[KeyBuffer]) -> GResult<()> {
+    let max_key = key_buffers[key_buffers.len() - 1].key;
+    for (lo, hi) in [(0, KeyT::MAX), (0, 0), (17, 17), (100, 5000), (max_key - 1000, max_key + 1000), (max_key / 3, max_key / 2), (50, 10)] {
+      let expected: Vec<(KeyT, Vec<u8>)> = key_buffers.iter()
+        .filter(|kb| lo <= kb.key && kb.key <= hi)
+        .map(|kb| (kb.key, kb.buffer[..].to_vec()))
+        .collect();
+      let scanned = kv_db.scan(lo, hi)?
+        .map(|kb| kb.map(|kb| (kb.key, kb.buffer[..].to_vec())))
+        .collect::<GResult<Vec<_>>>()?;
+      assert_eq!(scanned, expected, "Mismatched scan in [{}, {}]", lo, hi);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn scan_ok() -> GResult<()> {
+    let variable_kbs: Vec<KeyBuffer> = (1..3000)
+      .map(|idx| KeyBuffer::new(idx * 3, vec![idx as u8; idx as usize % 37]))
+      .collect();
+    assert_scans(&build_reload_in_memory(&variable_kbs)?, &variable_kbs)?;
+    let fixed_kbs: Vec<KeyBuffer> = (1..3000)
+      .map(|idx| KeyBuffer::new(idx * idx, (idx as u32).to_le_bytes().to_vec()))
+      .collect();
+    assert_scans(&build_reload_in_memory(&fixed_kbs)?, &fixed_kbs)
+  }
+
+  // predicts the range of the entry shift ranks away from the last one at or before the key
+  #[derive(Debug)]
+  struct ShiftedIndex {
+    krs: Vec<KeyPositionRange>,  // one per entry
+    shift: isize,
+  }
+
+  impl Index for ShiftedIndex {
+    fn predict(&self, key: &KeyT) -> GResult<KeyPositionRange> {
+      let rank = self.krs.partition_point(|kr| kr.key_l <= *key).saturating_sub(1);
+      let shifted_rank = (rank as isize + self.shift).clamp(0, self.krs.len() as isize - 1) as usize;
+      Ok(self.krs[shifted_rank].clone())
+    }
+
+    fn get_load(&self) -> Vec<LoadDistribution> {
+      Vec::new()
+    }
+  }
+
+  impl crate::index::IndexMetaserde for ShiftedIndex {
+    fn to_meta(&self, _ctx: &mut Context) -> GResult<IndexMeta> {
+      Err("ShiftedIndex is for tests only".into())
+    }
+  }
+
+  fn build_shifted_in_memory(key_buffers: &[KeyBuffer], shift: isize) -> GResult<KeyValueDB> {
+    let es = Arc::new(ExternalStorage::new().with("mem".to_string(), Box::new(MemoryAdaptor::new()))?);
+    let mut kv_db = KeyValueDB::new_designed(&es, Url::parse("mem:///data/")?, "kv".to_string(), key_buffers);
+    let kps = kv_db.write(key_buffers)?;
+    let krs = (0..kps.len()).map(|idx| kps.range_at(idx)).collect::<Result<Vec<_>, _>>()?;
+    kv_db.attach_index(Box::new(ShiftedIndex { krs, shift }));
+    Ok(kv_db)
+  }
+
+  #[test]
+  fn scan_mispredicted_ok() -> GResult<()> {
+    let variable_kbs: Vec<KeyBuffer> = (1..3000)
+      .map(|idx| KeyBuffer::new(idx * 3, vec![idx as u8; idx as usize % 37]))
+      .collect();
+    let fixed_kbs: Vec<KeyBuffer> = (1..3000)
+      .map(|idx| KeyBuffer::new(idx * idx, (idx as u32).to_le_bytes().to_vec()))
+      .collect();
+    for key_buffers in [variable_kbs, fixed_kbs] {
+      for shift in [-40, -1, 1, 40] {
+        assert_scans(&build_shifted_in_memory(&key_buffers, shift)?, &key_buffers)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn assert_neighbors(kv_db: &KeyValueDB, model: &BTreeMap<KeyT, Vec<u8>>, queries: &[KeyT]) -> GResult<()> {
+    let as_pair = |kb: Option<KeyBuffer>| kb.map(|kb| (kb.key, kb.buffer[..].to_vec()));
+    for &query in queries {
+      let expected_lower_bound = model.range(query..).next().map(|(key, value)| (*key, value.clone()));
+      let expected_predecessor = model.range(..=query).next_back().map(|(key, value)| (*key, value.clone()));
+      let expected_successor = model.range(query..).find(|(key, _)| **key > query).map(|(key, value)| (*key, value.clone()));
+      assert_eq!(as_pair(kv_db.lower_bound(query)?), expected_lower_bound, "Mismatched lower bound of {}", query);
+      assert_eq!(as_pair(kv_db.predecessor(query)?), expected_predecessor, "Mismatched predecessor of {}", query);
+      assert_eq!(as_pair(kv_db.successor(query)?), expected_successor, "Mismatched successor of {}", query);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn neighbors_ok() -> GResult<()> {
+    let variable_kbs: Vec<KeyBuffer> = (1..3000)
+      .map(|idx| KeyBuffer::new(idx * 3, vec![idx as u8; idx as usize % 37]))
+      .collect();
+    let fixed_kbs: Vec<KeyBuffer> = (1..3000)
+      .map(|idx| KeyBuffer::new(idx * idx, (idx as u32).to_le_bytes().to_vec()))
+      .collect();
+    for key_buffers in [variable_kbs, fixed_kbs] {
+      let model: BTreeMap<KeyT, Vec<u8>> = key_buffers.iter()
+        .map(|kb| (kb.key, kb.buffer[..].to_vec()))
+        .collect();
+      let mut queries: Vec<KeyT> = key_buffers.iter()
+        .step_by(7)
+        .flat_map(|kb| [kb.key - 1, kb.key, kb.key + 1])
+        .collect();
+      queries.extend([0, 1, key_buffers[key_buffers.len() - 1].key + 1000]);
+      assert_neighbors(&build_reload_in_memory(&key_buffers)?, &model, &queries)?;
+
+      // neighbors beyond predicted ranges on either side
+      for shift in [-40, -1, 1, 40] {
+        assert_neighbors(&build_shifted_in_memory(&key_buffers, shift)?, &model, &queries)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn assert_matches_model(kv_db: &KeyValueDB, model: &BTreeMap<KeyT, Vec<u8>>) -> GResult<()> {
+    for key in 0..10000 {
+      assert_eq!(kv_db.get(key)?.as_ref(), model.get(&key), "Mismatched value of key {}", key);
+    }
+    for (lo, hi) in [(0, KeyT::MAX), (0, 0), (100, 5000), (2999, 9000), (50, 10)] {
+      let expected: Vec<(KeyT, Vec<u8>)> = model.range(lo..=std::cmp::max(lo, hi))
+        .filter(|_| lo <= hi)
+        .map(|(key, value)| (*key, value.clone()))
+        .collect();
+      let scanned = kv_db.scan(lo, hi)?
+        .map(|kb| kb.map(|kb| (kb.key, kb.buffer[..].to_vec())))
+        .collect::<GResult<Vec<_>>>()?;
+      assert_eq!(scanned, expected, "Mismatched scan in [{}, {}]", lo, hi);
+    }
+    assert_neighbors(kv_db, model, &(0..6000).step_by(11).collect::<Vec<KeyT>>())
+  }
+
+  #[test]
+  fn updates_ok() -> GResult<()> {
+    let es = Arc::new(ExternalStorage::new().with("mem".to_string(), Box::new(MemoryAdaptor::new()))?);
+    let log_url = Url::parse("mem:///delta/")?;
+    let key_buffers: Vec<KeyBuffer> = (1..1000)
+      .map(|idx| KeyBuffer::new(idx * 3, vec![idx as u8; idx as usize % 37]))
+      .collect();
+    let mut model: BTreeMap<KeyT, Vec<u8>> = key_buffers.iter()
+      .map(|kb| (kb.key, kb.buffer[..].to_vec()))
+      .collect();
+    let mut kv_db = KeyValueDB::new_designed(&es, Url::parse("mem:///data/")?, "kv".to_string(), &key_buffers)
+      .with_delta_log(DeltaLog::new(&es, log_url.clone()));
+    let kps = kv_db.write(&key_buffers)?;
+    let profile = AffineStorageProfile::new(Latency::from_micros(100), Bandwidth::from_mbps(100.0));
+    let drafter = StepMultipleDrafter::exponentiation(256, 1024, 4.0, 16).to_serial();
+    let index_builder = BalanceStackIndexBuilder::new(&es, Box::new(drafter), &profile, Url::parse("mem:///db/")?);
+    kv_db.build_index(&kps, &index_builder)?;
+
+    // appends, inserts between keys, overwrites and deletes, some before the smallest key
+    for key in (0..5000).step_by(7) {
+      let value = vec![(key % 251) as u8; (key % 13) as usize];
+      kv_db.put(key, value.clone())?;
+      model.insert(key, value);
+    }
+    for key in (0..4000).step_by(5) {
+      kv_db.delete(key);
+      model.remove(&key);
+    }
+    kv_db.sync()?;
+    kv_db.put(6, vec![6])?;
+    model.insert(6, vec![6]);
+    assert_matches_model(&kv_db, &model)?;
+
+    // buffered updates survive reload through the log
+    let mut data_ctx = Context::new();
+    let mut index_ctx = Context::new();
+    let meta_bytes = meta::serialize(&kv_db.to_meta(&mut data_ctx, &mut index_ctx)?)?;
+    let mut kv_db = KeyValueDB::from_meta(meta::deserialize(&meta_bytes)?, &data_ctx, &index_ctx)?;
+    assert_matches_model(&kv_db, &model)?;
+
+    kv_db.compact(&index_builder)?;
+    assert!(kv_db.delta.is_empty());
+    assert!(es.read_all(&log_url.join("delta_00000000")?).is_err(), "Expected log truncated");
+    assert_matches_model(&kv_db, &model)
+  }
+
+  #[test]
+  fn append_compaction_ok() -> GResult<()> {
+    let variable_kbs: Vec<KeyBuffer> = (1..1000)
+      .map(|idx| KeyBuffer::new(idx * 3, vec![idx as u8; idx as usize % 37]))
+      .collect();
+    let fixed_kbs: Vec<KeyBuffer> = (1..1000)
+      .map(|idx| KeyBuffer::new(idx * 3, (idx as u32).to_le_bytes().to_vec()))
+      .collect();
+    for key_buffers in [variable_kbs, fixed_kbs] {
+      let es = Arc::new(ExternalStorage::new().with("mem".to_string(), Box::new(MemoryAdaptor::new()))?);
+      let value_size = key_buffers[0].buffer.len();
+      let is_fixed = key_buffers.iter().all(|kb| kb.buffer.len() == value_size);
+      let mut model: BTreeMap<KeyT, Vec<u8>> = key_buffers.iter()
+        .map(|kb| (kb.key, kb.buffer[..].to_vec()))
+        .collect();
+      let mut kv_db = KeyValueDB::new_designed(&es, Url::parse("mem:///data/")?, "kv".to_string(), &key_buffers);
+      let kps = kv_db.write(&key_buffers)?;
+      let profile = AffineStorageProfile::new(Latency::from_micros(100), Bandwidth::from_mbps(100.0));
+      let drafter = StepMultipleDrafter::exponentiation(256, 1024, 4.0, 16).to_serial();
+      let index_builder = BalanceStackIndexBuilder::new(&es, Box::new(drafter), &profile, Url::parse("mem:///db/")?);
+      kv_db.build_index(&kps, &index_builder)?;
+
+      // daily appends past the largest key, each indexed as a run
+      for (day, day_keys) in [(3000..4500).step_by(2), (4600..4601).step_by(1), (5000..6000).step_by(3)].into_iter().enumerate() {
+        for key in day_keys {
+          let value = if is_fixed { (key as u32).to_le_bytes().to_vec() } else { vec![(key % 251) as u8; (key % 13) as usize] };
+          kv_db.put(key, value.clone())?;
+          model.insert(key, value);
+        }
+        kv_db.delete(9999);
+        kv_db.compact(&index_builder)?;
+        assert_eq!(kv_db.appended_runs.len(), day + 1);
+        assert!(kv_db.delta.is_empty());
+        assert_matches_model(&kv_db, &model)?;
+      }
+
+      // runs survive reload
+      let mut data_ctx = Context::new();
+      let mut index_ctx = Context::new();
+      let meta_bytes = meta::serialize(&kv_db.to_meta(&mut data_ctx, &mut index_ctx)?)?;
+      let mut kv_db = KeyValueDB::from_meta(meta::deserialize(&meta_bytes)?, &data_ctx, &index_ctx)?;
+      assert_matches_model(&kv_db, &model)?;
+
+      // updates within stored keys fall back to a full rewrite
+      kv_db.delete(3000);
+      model.remove(&3000);
+      kv_db.compact(&index_builder)?;
+      assert!(kv_db.appended_runs.is_empty());
+      assert_matches_model(&kv_db, &model)?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn unsorted_write_err() -> GResult<()> {
+    let es = Arc::new(ExternalStorage::new().with("mem".to_string(), Box::new(MemoryAdaptor::new()))?);
+    let key_buffers = vec![KeyBuffer::new(2, vec![1]), KeyBuffer::new(2, vec![2])];
+    let mut kv_db = KeyValueDB::new_designed(&es, Url::parse("mem:///data/")?, "kv".to_string(), &key_buffers);
+    assert!(kv_db.write(&key_buffers).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn mismatched_value_size_err() -> GResult<()> {
+    let es = Arc::new(ExternalStorage::new().with("mem".to_string(), Box::new(MemoryAdaptor::new()))?);
+    let key_buffers = vec![KeyBuffer::new(1, vec![1; 4]), KeyBuffer::new(2, vec![2; 4])];
+    let mut kv_db = KeyValueDB::new_designed(&es, Url::parse("mem:///data/")?, "kv".to_string(), &key_buffers);
+    assert!(kv_db.put(3, vec![3; 5]).is_err());
+    assert_eq!(kv_db.get(3)?, None);
+    kv_db.put(3, vec![3; 4])?;
+    assert!(kv_db.write(&[KeyBuffer::new(1, vec![1; 4]), KeyBuffer::new(2, vec![2])]).is_err());
+    Ok(())
+  }
+}
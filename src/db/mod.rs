@@ -1 +1,3 @@
+pub mod delta;
 pub mod key_rank;
+pub mod key_value;
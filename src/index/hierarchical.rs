@@ -1,6 +1,5 @@
 use serde::{Serialize, Deserialize};
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 use url::Url;
 
@@ -16,6 +15,7 @@ use crate::index::piecewise::PiecewiseIndex;
 use crate::index::stash::StashIndex;
 use crate::io::internal::ExternalStorage;
 use crate::io::profile::StorageProfile;
+use crate::io::profile::TieredStorageProfile;
 use crate::io::storage::DummyAdaptor;
 use crate::meta::Context;
 use crate::model::load::LoadDistribution;
@@ -43,6 +43,11 @@ impl Index for StackIndex {
     self.lower_index.predict_within(&kr)
   }
 
+  fn predict_many(&self, keys: &[KeyT]) -> GResult<Vec<KeyPositionRange>> {
+    let krs = self.upper_index.predict_many(keys)?;
+    self.lower_index.predict_within_many(&krs)
+  }
+
   fn get_load(&self) -> Vec<LoadDistribution> {
     [self.upper_index.get_load(), self.lower_index.get_load()].concat()
   }
@@ -50,16 +55,16 @@ impl Index for StackIndex {
 
 #[derive(Debug)]
 pub struct BalanceStackIndexBuilder<'a> {
-  storage: Rc<RefCell<ExternalStorage>>,
+  storage: Arc<ExternalStorage>,
   drafter: Box<dyn ModelDrafter>,
   profile: &'a dyn StorageProfile,
   prefix_url: Url,
 }
 
 impl<'a> BalanceStackIndexBuilder<'a> {
-  pub fn new(storage: &Rc<RefCell<ExternalStorage>>, drafter: Box<dyn ModelDrafter>, profile: &'a dyn StorageProfile, prefix_url: Url) -> BalanceStackIndexBuilder<'a> {
+  pub fn new(storage: &Arc<ExternalStorage>, drafter: Box<dyn ModelDrafter>, profile: &'a dyn StorageProfile, prefix_url: Url) -> BalanceStackIndexBuilder<'a> {
     BalanceStackIndexBuilder {
-      storage: Rc::clone(storage),
+      storage: Arc::clone(storage),
       drafter,
       profile,
       prefix_url,
@@ -73,6 +78,7 @@ impl<'a> BalanceStackIndexBuilder<'a> {
     kps: &KeyPositionCollection,
     layer_idx: usize,
     lower_data_store: Option<&dyn DataStore>,
+    name: &str,
   ) -> GResult<Box<dyn Index>> {
     // if no index is built
     let no_index_cost = self.profile.cost(kps.total_bytes());
@@ -84,7 +90,7 @@ impl<'a> BalanceStackIndexBuilder<'a> {
     if model_draft.cost < no_index_cost {
       // persist
       let data_store = StoreDesigner::new(&self.storage)
-        .design_for_kbs(&model_draft.key_buffers, self.prefix_url.clone(), self.layer_name(layer_idx));
+        .design_for_kbs(&model_draft.key_buffers, self.prefix_url.clone(), self.layer_name(name, layer_idx));
       let (piecewise_index, lower_index_kps) = PiecewiseIndex::craft(model_draft, data_store)?;
 
       // try next
@@ -92,6 +98,7 @@ impl<'a> BalanceStackIndexBuilder<'a> {
         &lower_index_kps,
         layer_idx + 1,
         Some(piecewise_index.borrow_data_store()),
+        name,
       )?;
       let lower_index = Box::new(piecewise_index) as Box<dyn PartialIndex>;
       Ok(Box::new(StackIndex {
@@ -105,20 +112,20 @@ impl<'a> BalanceStackIndexBuilder<'a> {
     }
   }
 
-  fn layer_name(&self, layer_idx: usize) -> String {
-    format!("layer_{}", layer_idx)
+  fn layer_name(&self, name: &str, layer_idx: usize) -> String {
+    format!("{}layer_{}", name, layer_idx)
   }
 }
 
 impl<'a> IndexBuilder for BalanceStackIndexBuilder<'a> {
-  fn build_index(&self, kps: &KeyPositionCollection) -> GResult<Box<dyn Index>> {
-    self.bns_at_layer(kps, 1, None)
+  fn build_index_named(&self, kps: &KeyPositionCollection, name: &str) -> GResult<Box<dyn Index>> {
+    self.bns_at_layer(kps, 1, None, name)
   }
 }
 
 #[derive(Debug)]
 pub struct BoundedTopStackIndexBuilder<'a> {
-  storage: Rc<RefCell<ExternalStorage>>,
+  storage: Arc<ExternalStorage>,
   drafter: Box<dyn ModelDrafter>,
   profile: &'a dyn StorageProfile,
   top_load: usize,
@@ -126,9 +133,9 @@ pub struct BoundedTopStackIndexBuilder<'a> {
 }
 
 impl<'a> BoundedTopStackIndexBuilder<'a> {
-  pub fn new(storage: &Rc<RefCell<ExternalStorage>>, drafter: Box<dyn ModelDrafter>, profile: &'a dyn StorageProfile, top_load: usize, prefix_url: Url) -> BoundedTopStackIndexBuilder<'a> {
+  pub fn new(storage: &Arc<ExternalStorage>, drafter: Box<dyn ModelDrafter>, profile: &'a dyn StorageProfile, top_load: usize, prefix_url: Url) -> BoundedTopStackIndexBuilder<'a> {
     BoundedTopStackIndexBuilder {
-      storage: Rc::clone(storage),
+      storage: Arc::clone(storage),
       drafter,
       profile,
       top_load,
@@ -143,6 +150,7 @@ impl<'a> BoundedTopStackIndexBuilder<'a> {
     kps: &KeyPositionCollection,
     layer_idx: usize,
     lower_data_store: Option<&dyn DataStore>,
+    name: &str,
   ) -> GResult<Box<dyn Index>> {
     log::info!("Check total bytes {} <==> {}", kps.total_bytes(), self.top_load);
     if kps.total_bytes() > self.top_load {
@@ -151,7 +159,7 @@ impl<'a> BoundedTopStackIndexBuilder<'a> {
 
       // persist
       let data_store = StoreDesigner::new(&self.storage)
-        .design_for_kbs(&model_draft.key_buffers, self.prefix_url.clone(), self.layer_name(layer_idx));
+        .design_for_kbs(&model_draft.key_buffers, self.prefix_url.clone(), self.layer_name(name, layer_idx));
       let (piecewise_index, lower_index_kps) = PiecewiseIndex::craft(model_draft, data_store)?;
 
       // try next
@@ -159,6 +167,7 @@ impl<'a> BoundedTopStackIndexBuilder<'a> {
         &lower_index_kps,
         layer_idx + 1,
         Some(piecewise_index.borrow_data_store()),
+        name,
       )?;
       let lower_index = Box::new(piecewise_index) as Box<dyn PartialIndex>;
       Ok(Box::new(StackIndex {
@@ -175,14 +184,14 @@ impl<'a> BoundedTopStackIndexBuilder<'a> {
     }
   }
 
-  fn layer_name(&self, layer_idx: usize) -> String {
-    format!("layer_{}", layer_idx)
+  fn layer_name(&self, name: &str, layer_idx: usize) -> String {
+    format!("{}layer_{}", name, layer_idx)
   }
 }
 
 impl<'a> IndexBuilder for BoundedTopStackIndexBuilder<'a> {
-  fn build_index(&self, kps: &KeyPositionCollection) -> GResult<Box<dyn Index>> {
-    self.bts_at_layer(kps, 1, None)
+  fn build_index_named(&self, kps: &KeyPositionCollection, name: &str) -> GResult<Box<dyn Index>> {
+    self.bts_at_layer(kps, 1, None, name)
   }
 }
 
@@ -215,34 +224,36 @@ impl StackIndex {  // for Metaserde
 
 #[derive(Debug)]
 pub struct ExploreStackIndexBuilder<'a> {
-  storage: Rc<RefCell<ExternalStorage>>,
+  storage: Arc<ExternalStorage>,
   drafter: Box<dyn ModelDrafter>,
   profile: &'a dyn StorageProfile,
   prefix_url: Url,
 
   // For generating kps without actually writing to storage
-  dummy_storage: Rc<RefCell<ExternalStorage>>,
+  dummy_storage: Arc<ExternalStorage>,
   dummy_prefix_url: Url,
 
   target_layers: Option<usize>,  // if set, only build index with many layers
 
   top_k_candidates: usize,
+
+  cache_hit_profile: Option<Box<dyn StorageProfile>>,  // if set, cache-resident reads cost as this
 }
 
 impl<'a> ExploreStackIndexBuilder<'a> {
   // explore all model drafts in many layers
   pub fn new(
-    storage: &Rc<RefCell<ExternalStorage>>,
+    storage: &Arc<ExternalStorage>,
     drafter: Box<dyn ModelDrafter>,
     profile: &'a dyn StorageProfile,
     prefix_url: Url
   ) -> ExploreStackIndexBuilder<'a> {
-    let dummy_storage = Rc::new(RefCell::new(ExternalStorage::new()
+    let dummy_storage = Arc::new(ExternalStorage::new()
       .with("dummy".to_string(), Box::new(DummyAdaptor::default()))
       .expect("Failed to initiate dummy storage")
-    ));
+    );
     ExploreStackIndexBuilder {
-      storage: Rc::clone(storage),
+      storage: Arc::clone(storage),
       drafter,
       profile,
       prefix_url,
@@ -250,23 +261,24 @@ impl<'a> ExploreStackIndexBuilder<'a> {
       dummy_prefix_url: Url::parse("dummy:///index").unwrap(),
       target_layers: None,
       top_k_candidates: 5,
+      cache_hit_profile: None,
     }
   }
 
   // build at an exactly target number of layers
   pub fn exact_layers(
-    storage: &Rc<RefCell<ExternalStorage>>,
+    storage: &Arc<ExternalStorage>,
     drafter: Box<dyn ModelDrafter>,
     profile: &'a dyn StorageProfile,
     prefix_url: Url,
     target_layers: usize,
   ) -> ExploreStackIndexBuilder<'a> {
-    let dummy_storage = Rc::new(RefCell::new(ExternalStorage::new()
+    let dummy_storage = Arc::new(ExternalStorage::new()
       .with("dummy".to_string(), Box::new(DummyAdaptor::default()))
       .expect("Failed to initiate dummy storage")
-    ));
+    );
     ExploreStackIndexBuilder {
-      storage: Rc::clone(storage),
+      storage: Arc::clone(storage),
       drafter,
       profile,
       prefix_url,
@@ -274,6 +286,7 @@ impl<'a> ExploreStackIndexBuilder<'a> {
       dummy_prefix_url: Url::parse("dummy:///index").unwrap(),
       target_layers: Some(target_layers),
       top_k_candidates: 5,
+      cache_hit_profile: None,
     }
   }
 
@@ -282,6 +295,28 @@ impl<'a> ExploreStackIndexBuilder<'a> {
     self
   }
 
+  pub fn set_cache_hit_profile(mut self, cache_hit_profile: Box<dyn StorageProfile>) -> Self {
+    self.cache_hit_profile = Some(cache_hit_profile);
+    self
+  }
+
+  // expected cost of reading a layer of layer_bytes, assuming uniform reads and that cache
+  // goes to upper layers first (every lookup reads them), leaving what upper_bytes did not take
+  fn layer_profile(&self, layer_bytes: usize, upper_bytes: usize) -> Box<dyn StorageProfile> {
+    match &self.cache_hit_profile {
+      Some(cache_hit_profile) => {
+        let cache_capacity = self.storage.cache_capacity().saturating_sub(upper_bytes);
+        let hit_probability = if layer_bytes <= cache_capacity {
+          1.0
+        } else {
+          cache_capacity as f64 / layer_bytes as f64
+        };
+        Box::new(TieredStorageProfile::new(cache_hit_profile.clone_box(), self.profile.clone_box(), hit_probability))
+      },
+      None => self.profile.clone_box(),
+    }
+  }
+
   fn summarize_loads(&self, loads: &[LoadDistribution]) -> Vec<usize> {
     // TODO: configurable?
     loads.iter()
@@ -307,20 +342,23 @@ impl<'a> ExploreStackIndexBuilder<'a> {
     &self,
     kps: &KeyPositionCollection,
     layer_idx: usize,
-  ) -> GResult<(Vec<ModelDraft>, Duration)> {
-    // decide whether to continue
-    let no_index_cost = self.profile.cost(kps.total_bytes());
-    let ideal_index_cost = self.profile.sequential_cost(&[1, 1]);
+  ) -> GResult<(Vec<ModelDraft>, Duration, usize)> {  // also total bytes of kps and layers above
+    // decide whether to continue, ideally the upper layer is tiny thus resident
+    // before knowing upper layers, assume all cache is left for this one
+    let profile = self.layer_profile(kps.total_bytes(), 0);
+    let upper_ideal_cost = self.layer_profile(0, 0).cost(1);
+    let no_index_cost = profile.cost(kps.total_bytes());
+    let ideal_index_cost = upper_ideal_cost + profile.cost(1);
 
     if self.should_build(&no_index_cost, &ideal_index_cost, layer_idx) {
       let mut maybe_drafts = None;
-      let mut drafts = self.drafter.draft_many(kps, self.profile);
+      let mut drafts = self.drafter.draft_many(kps, profile.as_ref());
       drafts.sort_by_key(|draft| draft.cost);
       for model_draft in drafts.into_iter().take(self.top_k_candidates) {
         // calculate cost at this layer
         let current_loads = self.summarize_loads(&model_draft.serde.get_load());
-        let current_costs = self.profile.sequential_cost(&current_loads);
-        let current_ideal_cost = self.profile.sequential_cost(&[vec![1], current_loads].concat());
+        let current_costs = profile.sequential_cost(&current_loads);
+        let current_ideal_cost = upper_ideal_cost + current_costs;
         if !self.should_build(&no_index_cost, &current_ideal_cost, layer_idx) {
           continue;
         }
@@ -337,29 +375,31 @@ impl<'a> ExploreStackIndexBuilder<'a> {
         }
 
         // try next layer
-        if let Ok((mut model_drafts, upper_cost)) = self.ens_at_layer(&current_kps, layer_idx + 1) {
+        if let Ok((mut model_drafts, upper_cost, upper_bytes)) = self.ens_at_layer(&current_kps, layer_idx + 1) {
           model_drafts.push(model_draft);
-          let total_cost = upper_cost + current_costs;
+          let allocated_costs = self.layer_profile(kps.total_bytes(), upper_bytes).sequential_cost(&current_loads);
+          let total_cost = upper_cost + allocated_costs;
 
           // decide whether to use this draft
           if layer_idx == 1 {
             self.log_draft("Candidate", &model_drafts, &total_cost);
           }
+          let total_bytes = upper_bytes + kps.total_bytes();
           maybe_drafts = match maybe_drafts {
-            Some((best_drafts, best_cost)) => if best_cost < total_cost {
-              Some((best_drafts, best_cost))
+            Some((best_drafts, best_cost, best_bytes)) => if best_cost < total_cost {
+              Some((best_drafts, best_cost, best_bytes))
             } else {
-              Some((model_drafts, total_cost))
+              Some((model_drafts, total_cost, total_bytes))
             },
-            None => Some((model_drafts, total_cost))
+            None => Some((model_drafts, total_cost, total_bytes))
           }
         }
       }
 
       // return if beneficial
-      if let Some((model_drafts, best_index_cost)) = maybe_drafts {
+      if let Some((model_drafts, best_index_cost, best_bytes)) = maybe_drafts {
         if self.should_build(&no_index_cost, &best_index_cost, layer_idx) {
-          return Ok((model_drafts, best_index_cost))
+          return Ok((model_drafts, best_index_cost, best_bytes))
         }
       }
     }
@@ -372,7 +412,7 @@ impl<'a> ExploreStackIndexBuilder<'a> {
     }
 
     // fetching whole data layer is faster than building index, no further index to build
-    Ok((Vec::new(), no_index_cost))
+    Ok((Vec::new(), no_index_cost, kps.total_bytes()))
     
   }
 
@@ -382,10 +422,11 @@ impl<'a> ExploreStackIndexBuilder<'a> {
     layer_idx: usize,
     kps: &KeyPositionCollection,
     lower_data_store: Option<&dyn DataStore>,
+    name: &str,
   ) -> GResult<Box<dyn Index>> {
     if let Some(current_model_draft) = model_drafts.pop() {
       // write current draft to storage
-      let current_data_store = self.make_data_store(&current_model_draft.key_buffers, layer_idx);
+      let current_data_store = self.make_data_store(&current_model_draft.key_buffers, layer_idx, name);
       let (current_index, current_kps) = PiecewiseIndex::craft(current_model_draft, current_data_store)?;
 
       // continue to write upper index
@@ -394,6 +435,7 @@ impl<'a> ExploreStackIndexBuilder<'a> {
         layer_idx + 1,
         &current_kps,
         Some(current_index.borrow_data_store()),
+        name,
       )?;
 
       // compose upper layers with current layer
@@ -411,12 +453,12 @@ impl<'a> ExploreStackIndexBuilder<'a> {
     }
   }
 
-  fn make_data_store(&self, key_buffers: &[KeyBuffer], layer_idx: usize) -> Box<dyn DataStore> {
+  fn make_data_store(&self, key_buffers: &[KeyBuffer], layer_idx: usize, name: &str) -> Box<dyn DataStore> {
     StoreDesigner::new(&self.storage)
       .design_for_kbs(
         key_buffers,
         self.prefix_url.clone(),
-        self.layer_name(layer_idx),
+        self.layer_name(name, layer_idx),
       )
   }
 
@@ -425,12 +467,12 @@ impl<'a> ExploreStackIndexBuilder<'a> {
       .design_for_kbs(
         key_buffers,
         self.dummy_prefix_url.clone(),
-        self.layer_name(layer_idx),
+        self.layer_name("", layer_idx),
       )
   }
 
-  fn layer_name(&self, layer_idx: usize) -> String {
-    format!("layer_{}", layer_idx)
+  fn layer_name(&self, name: &str, layer_idx: usize) -> String {
+    format!("{}layer_{}", name, layer_idx)
   }
 
   fn log_draft(&self, prefix: &str, model_drafts: &[ModelDraft], total_cost: &Duration) {
@@ -444,9 +486,9 @@ impl<'a> ExploreStackIndexBuilder<'a> {
 }
 
 impl<'a> IndexBuilder for ExploreStackIndexBuilder<'a> {
-  fn build_index(&self, kps: &KeyPositionCollection) -> GResult<Box<dyn Index>> {
-    let (model_drafts, best_cost) = self.ens_at_layer(kps, 1)?;  // root, ..., layer 1
+  fn build_index_named(&self, kps: &KeyPositionCollection, name: &str) -> GResult<Box<dyn Index>> {
+    let (model_drafts, best_cost, _) = self.ens_at_layer(kps, 1)?;  // root, ..., layer 1
     self.log_draft("Best draft", &model_drafts, &best_cost);
-    self.craft_all(model_drafts, 1, kps, None)
+    self.craft_all(model_drafts, 1, kps, None, name)
   }
 }
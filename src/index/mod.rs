@@ -11,17 +11,32 @@ use crate::store::key_position::KeyT;
 
 /* Index traits */
 
-pub trait Index: IndexMetaserde + Debug {
+pub trait Index: IndexMetaserde + Debug + Send + Sync {
   fn predict(&self, key: &KeyT) -> GResult<KeyPositionRange>;
   fn get_load(&self) -> Vec<LoadDistribution>;
+
+  // keys sorted, indexes reading from storage share reads among nearby keys
+  fn predict_many(&self, keys: &[KeyT]) -> GResult<Vec<KeyPositionRange>> {
+    keys.iter().map(|key| self.predict(key)).collect()
+  }
 }
 
 pub trait PartialIndex: PartialIndexMetaserde + Index {
   fn predict_within(&self, kr: &KeyPositionRange) -> GResult<KeyPositionRange>;
+
+  // ranges sorted by key
+  fn predict_within_many(&self, krs: &[KeyPositionRange]) -> GResult<Vec<KeyPositionRange>> {
+    krs.iter().map(|kr| self.predict_within(kr)).collect()
+  }
 }
 
 pub trait IndexBuilder: Debug {
-  fn build_index(&self, kps: &KeyPositionCollection) -> GResult<Box<dyn Index>>;
+  // layer names start with name, so that many indexes can share one prefix
+  fn build_index_named(&self, kps: &KeyPositionCollection, name: &str) -> GResult<Box<dyn Index>>;
+
+  fn build_index(&self, kps: &KeyPositionCollection) -> GResult<Box<dyn Index>> {
+    self.build_index_named(kps, "")
+  }
 }
 
 pub mod piecewise;
@@ -35,7 +35,7 @@ impl PiecewiseIndex {
     self.data_store.as_ref()
   }
 
-  fn predict_from_reader(&self, reader: Box<dyn DataStoreReader>, key: &KeyT) -> GResult<KeyPositionRange> {
+  fn predict_from_reader(&self, reader: &dyn DataStoreReader, key: &KeyT) -> GResult<KeyPositionRange> {
     let model_kb = PiecewiseIndex::select_relevant_kb(reader, key)?;
     // tracing::trace!("piecewise_find");
     let model = self.model_serde.reconstruct(&model_kb.buffer[..])?;
@@ -46,7 +46,7 @@ impl PiecewiseIndex {
     Ok(kpr)
   }
 
-  fn select_relevant_kb(reader: Box<dyn DataStoreReader>, key: &KeyT) -> GResult<KeyBuffer> {
+  fn select_relevant_kb(reader: &dyn DataStoreReader, key: &KeyT) -> GResult<KeyBuffer> {
     reader.first_of(*key)
     // // assuming key-buffers are sorted by key
     // let last_kb = reader.iter().take_while(|kb| kb.key <= *key).last();
@@ -62,7 +62,12 @@ impl Index for PiecewiseIndex {
   fn predict(&self, key: &KeyT) -> GResult<KeyPositionRange> {
     let reader = self.data_store.read_all()?;
     log::trace!("Received piecewise buffer");  // TEMP
-    self.predict_from_reader(reader, key)
+    self.predict_from_reader(reader.as_ref(), key)
+  }
+
+  fn predict_many(&self, keys: &[KeyT]) -> GResult<Vec<KeyPositionRange>> {
+    let reader = self.data_store.read_all()?;
+    keys.iter().map(|key| self.predict_from_reader(reader.as_ref(), key)).collect()
   }
 
   fn get_load(&self) -> Vec<LoadDistribution> {
@@ -74,7 +79,19 @@ impl PartialIndex for PiecewiseIndex {
   fn predict_within(&self, kr: &KeyPositionRange) -> GResult<KeyPositionRange> {
     let reader = self.data_store.read_within(kr.offset, kr.length)?;
     log::trace!("Received piecewise buffer, partial {:?}", kr);  // TEMP
-    self.predict_from_reader(reader, &kr.key_l)
+    self.predict_from_reader(reader.as_ref(), &kr.key_l)
+  }
+
+  fn predict_within_many(&self, krs: &[KeyPositionRange]) -> GResult<Vec<KeyPositionRange>> {
+    // one read per run of overlapping ranges, the wider buffer still holds each key's model
+    let mut predicted_krs = Vec::with_capacity(krs.len());
+    for (idxs, offset, length) in KeyPositionRange::group_overlapping(krs) {
+      let reader = self.data_store.read_within(offset, length)?;
+      for kr in &krs[idxs] {
+        predicted_krs.push(self.predict_from_reader(reader.as_ref(), &kr.key_l)?);
+      }
+    }
+    Ok(predicted_krs)
   }
 }
 
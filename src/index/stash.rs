@@ -1,7 +1,6 @@
-use core::cell::RefCell;
 use crate::store::DataStore;
 use serde::{Serialize, Deserialize};
-use std::rc::Rc;
+use std::sync::Arc;
 use url::Url;
 
 use crate::common::SharedBytes;
@@ -26,9 +25,9 @@ struct Stash {
 }
 
 impl Stash {
-  fn new(path: String, storage: &Rc<RefCell<ExternalStorage>>, prefix_url: &Url) -> GResult<Stash> {
+  fn new(path: String, storage: &Arc<ExternalStorage>, prefix_url: &Url) -> GResult<Stash> {
     let url = prefix_url.join(&path)?;
-    let buffer = storage.borrow().read_all(&url)?;
+    let buffer = storage.read_all(&url)?;
     Ok(Stash { path, buffer })
   }
 
@@ -37,7 +36,7 @@ impl Stash {
       let url = ctx.store_prefix.as_ref()
         .expect("Applying stash require store_prefix")
         .join(&self.path)?;
-      storage.borrow().warm_cache(&url, &self.buffer.slice_all());
+      storage.warm_cache(&url, &self.buffer.slice_all());
     }
     Ok(())
   }
@@ -67,7 +66,7 @@ impl StashIndex {
   pub fn build(
     kps: &KeyPositionCollection, 
     data_store: Option<&dyn DataStore>,  // to be stashed
-    storage: &Rc<RefCell<ExternalStorage>>,  // source of target data
+    storage: &Arc<ExternalStorage>,  // source of target data
     prefix_url: &Url,
   ) -> GResult<StashIndex> {
     let (start_position, end_position) = kps.whole_range();
@@ -77,7 +76,7 @@ impl StashIndex {
 
   fn stash(
     data_store: Option<&dyn DataStore>,  // to be stashed
-    storage: &Rc<RefCell<ExternalStorage>>,  // source of target data
+    storage: &Arc<ExternalStorage>,  // source of target data
     prefix_url: &Url,
   ) -> GResult<Vec<Stash>> {
     match data_store {
@@ -0,0 +1,740 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use url::Url;
+
+use crate::common::error::GenericError;
+use crate::common::error::UnknownCachePolicy;
+
+
+/* Eviction policy, tracks keys only while the cache owns the values */
+
+pub trait EvictionPolicy<K>: Debug + Send {
+  // key newly inserted into the cache
+  fn on_insert(&mut self, key: &K);
+  // key hit in the cache
+  fn on_access(&mut self, key: &K);
+  // key removed from the cache not through evict (e.g. invalidation)
+  fn on_remove(&mut self, key: &K);
+  // pick and forget a victim, none if tracking nothing
+  fn evict(&mut self) -> Option<K>;
+  fn clear(&mut self);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CachePolicy {
+  #[default]
+  Fifo,
+  Lru,
+  Clock,
+  TwoQ,
+}
+
+impl CachePolicy {
+  pub fn make<K: Clone + Eq + Hash + Debug + Send + 'static>(&self, capacity: usize) -> Box<dyn EvictionPolicy<K>> {
+    match self {
+      CachePolicy::Fifo => Box::new(FifoPolicy::default()),
+      CachePolicy::Lru => Box::new(LruPolicy::default()),
+      CachePolicy::Clock => Box::new(ClockPolicy::default()),
+      CachePolicy::TwoQ => Box::new(TwoQPolicy::new(capacity)),
+    }
+  }
+}
+
+impl FromStr for CachePolicy {
+  type Err = GenericError;
+
+  fn from_str(policy: &str) -> Result<Self, Self::Err> {
+    match policy {
+      "fifo" => Ok(CachePolicy::Fifo),
+      "lru" => Ok(CachePolicy::Lru),
+      "clock" => Ok(CachePolicy::Clock),
+      "2q" => Ok(CachePolicy::TwoQ),
+      _ => Err(UnknownCachePolicy::boxed(policy)),
+    }
+  }
+}
+
+
+/* Queue of unique keys, removable in the middle */
+
+#[derive(Debug)]
+struct KeyQueue<K> {
+  order: BTreeMap<u64, K>,  // sequence --> key, front is the oldest
+  seqs: HashMap<K, u64>,
+  next_seq: u64,
+}
+
+impl<K> Default for KeyQueue<K> {
+  fn default() -> Self {
+    KeyQueue { order: BTreeMap::new(), seqs: HashMap::new(), next_seq: 0 }
+  }
+}
+
+impl<K: Clone + Eq + Hash> KeyQueue<K> {
+  fn len(&self) -> usize {
+    self.seqs.len()
+  }
+
+  fn contains(&self, key: &K) -> bool {
+    self.seqs.contains_key(key)
+  }
+
+  // push to back, moving the key if already queued
+  fn push_back(&mut self, key: &K) {
+    self.remove(key);
+    self.order.insert(self.next_seq, key.clone());
+    self.seqs.insert(key.clone(), self.next_seq);
+    self.next_seq += 1;
+  }
+
+  fn pop_front(&mut self) -> Option<K> {
+    let seq = *self.order.keys().next()?;
+    let key = self.order.remove(&seq).unwrap();
+    self.seqs.remove(&key);
+    Some(key)
+  }
+
+  fn remove(&mut self, key: &K) -> bool {
+    match self.seqs.remove(key) {
+      Some(seq) => {
+        self.order.remove(&seq);
+        true
+      },
+      None => false,
+    }
+  }
+
+  fn clear(&mut self) {
+    self.order.clear();
+    self.seqs.clear();
+  }
+}
+
+
+/* First-in first-out */
+
+#[derive(Debug)]
+pub struct FifoPolicy<K> {
+  queue: KeyQueue<K>,
+}
+
+impl<K> Default for FifoPolicy<K> {
+  fn default() -> Self {
+    FifoPolicy { queue: KeyQueue::default() }
+  }
+}
+
+impl<K: Clone + Eq + Hash + Debug + Send> EvictionPolicy<K> for FifoPolicy<K> {
+  fn on_insert(&mut self, key: &K) {
+    self.queue.push_back(key);
+  }
+
+  fn on_access(&mut self, _key: &K) {}
+
+  fn on_remove(&mut self, key: &K) {
+    self.queue.remove(key);
+  }
+
+  fn evict(&mut self) -> Option<K> {
+    self.queue.pop_front()
+  }
+
+  fn clear(&mut self) {
+    self.queue.clear();
+  }
+}
+
+
+/* Least recently used */
+
+#[derive(Debug)]
+pub struct LruPolicy<K> {
+  queue: KeyQueue<K>,  // front is the least recently used
+}
+
+impl<K> Default for LruPolicy<K> {
+  fn default() -> Self {
+    LruPolicy { queue: KeyQueue::default() }
+  }
+}
+
+impl<K: Clone + Eq + Hash + Debug + Send> EvictionPolicy<K> for LruPolicy<K> {
+  fn on_insert(&mut self, key: &K) {
+    self.queue.push_back(key);
+  }
+
+  fn on_access(&mut self, key: &K) {
+    self.queue.push_back(key);
+  }
+
+  fn on_remove(&mut self, key: &K) {
+    self.queue.remove(key);
+  }
+
+  fn evict(&mut self) -> Option<K> {
+    self.queue.pop_front()
+  }
+
+  fn clear(&mut self) {
+    self.queue.clear();
+  }
+}
+
+
+/* CLOCK, second chance with a reference bit per slot */
+
+#[derive(Debug)]
+pub struct ClockPolicy<K> {
+  slots: Vec<Option<(K, bool)>>,  // key, referenced
+  slot_of: HashMap<K, usize>,
+  free_slots: Vec<usize>,
+  hand: usize,
+}
+
+impl<K> Default for ClockPolicy<K> {
+  fn default() -> Self {
+    ClockPolicy { slots: Vec::new(), slot_of: HashMap::new(), free_slots: Vec::new(), hand: 0 }
+  }
+}
+
+impl<K: Clone + Eq + Hash + Debug + Send> EvictionPolicy<K> for ClockPolicy<K> {
+  fn on_insert(&mut self, key: &K) {
+    if self.slot_of.contains_key(key) {
+      return self.on_access(key);
+    }
+    let slot = match self.free_slots.pop() {
+      Some(slot) => slot,
+      None => {
+        self.slots.push(None);
+        self.slots.len() - 1
+      },
+    };
+    self.slots[slot] = Some((key.clone(), false));
+    self.slot_of.insert(key.clone(), slot);
+  }
+
+  fn on_access(&mut self, key: &K) {
+    if let Some(slot) = self.slot_of.get(key) {
+      if let Some((_, referenced)) = &mut self.slots[*slot] {
+        *referenced = true;
+      }
+    }
+  }
+
+  fn on_remove(&mut self, key: &K) {
+    if let Some(slot) = self.slot_of.remove(key) {
+      self.slots[slot] = None;
+      self.free_slots.push(slot);
+    }
+  }
+
+  fn evict(&mut self) -> Option<K> {
+    if self.slot_of.is_empty() {
+      return None;
+    }
+    // at most two sweeps, the first clears reference bits
+    loop {
+      self.hand %= self.slots.len();
+      let slot = self.hand;
+      self.hand += 1;
+      match &mut self.slots[slot] {
+        Some((_, referenced)) if *referenced => *referenced = false,
+        Some(_) => {
+          let (key, _) = self.slots[slot].take().unwrap();
+          self.slot_of.remove(&key);
+          self.free_slots.push(slot);
+          return Some(key);
+        },
+        None => (),
+      }
+    }
+  }
+
+  fn clear(&mut self) {
+    self.slots.clear();
+    self.slot_of.clear();
+    self.free_slots.clear();
+    self.hand = 0;
+  }
+}
+
+
+/* 2Q, new keys wait in a fifo and only those re-referenced after eviction stay in lru */
+
+#[derive(Debug)]
+pub struct TwoQPolicy<K> {
+  recent: KeyQueue<K>,  // A1in, fifo of first-time keys
+  ghost: KeyQueue<K>,  // A1out, keys recently evicted from recent, no value
+  frequent: KeyQueue<K>,  // Am, lru of re-referenced keys
+  recent_capacity: usize,
+  ghost_capacity: usize,
+}
+
+impl<K> TwoQPolicy<K> {
+  pub fn new(capacity: usize) -> TwoQPolicy<K> {
+    // recommended tuning from the 2Q paper
+    TwoQPolicy {
+      recent: KeyQueue::default(),
+      ghost: KeyQueue::default(),
+      frequent: KeyQueue::default(),
+      recent_capacity: std::cmp::max(1, capacity / 4),
+      ghost_capacity: std::cmp::max(1, capacity / 2),
+    }
+  }
+}
+
+impl<K: Clone + Eq + Hash + Debug + Send> EvictionPolicy<K> for TwoQPolicy<K> {
+  fn on_insert(&mut self, key: &K) {
+    if self.ghost.remove(key) || self.frequent.contains(key) {
+      self.frequent.push_back(key);
+    } else if !self.recent.contains(key) {
+      self.recent.push_back(key);
+    }
+  }
+
+  fn on_access(&mut self, key: &K) {
+    // hits in recent stay put, correlated references should not promote
+    if self.frequent.contains(key) {
+      self.frequent.push_back(key);
+    }
+  }
+
+  fn on_remove(&mut self, key: &K) {
+    if !self.recent.remove(key) {
+      self.frequent.remove(key);
+    }
+  }
+
+  fn evict(&mut self) -> Option<K> {
+    if self.recent.len() > self.recent_capacity || self.frequent.len() == 0 {
+      let key = self.recent.pop_front()?;
+      self.ghost.push_back(&key);
+      while self.ghost.len() > self.ghost_capacity {
+        self.ghost.pop_front();
+      }
+      Some(key)
+    } else {
+      self.frequent.pop_front()
+    }
+  }
+
+  fn clear(&mut self) {
+    self.recent.clear();
+    self.ghost.clear();
+    self.frequent.clear();
+  }
+}
+
+
+/* Bounded cache, evicting by the given policy */
+
+pub struct Cache<K, V> {
+  total_size: usize,
+  pages: HashMap<K, V>,
+  policy: Box<dyn EvictionPolicy<K>>,
+}
+
+impl<K: Clone + Eq + Hash + Debug + Send + 'static, V> Cache<K, V> {
+  pub fn new(total_size: usize, policy: CachePolicy) -> Cache<K, V> {
+    Cache {
+      total_size,
+      pages: HashMap::new(),
+      policy: policy.make(total_size),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.pages.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.pages.is_empty()
+  }
+
+  pub fn get(&mut self, key: &K) -> Option<&V> {
+    if self.pages.contains_key(key) {
+      self.policy.on_access(key);
+    }
+    self.pages.get(key)
+  }
+
+  // check without counting as an access
+  pub fn contains(&self, key: &K) -> bool {
+    self.pages.contains_key(key)
+  }
+
+  // returns keys evicted to make room
+  pub fn put(&mut self, key: K, value: V) -> Vec<K> {
+    let mut victims = Vec::new();
+    if self.total_size == 0 {
+      return victims;
+    }
+    if self.pages.contains_key(&key) {
+      self.policy.on_access(&key);
+      self.pages.insert(key, value);
+      return victims;
+    }
+    while self.pages.len() >= self.total_size {
+      match self.policy.evict() {
+        Some(victim) => {
+          self.pages.remove(&victim);
+          victims.push(victim);
+        },
+        None => break,
+      }
+    }
+    self.policy.on_insert(&key);
+    self.pages.insert(key, value);
+    victims
+  }
+
+  pub fn remove(&mut self, key: &K) -> Option<V> {
+    let value = self.pages.remove(key);
+    if value.is_some() {
+      self.policy.on_remove(key);
+    }
+    value
+  }
+
+  pub fn clear(&mut self) {
+    self.policy.clear();
+    self.pages.clear();
+  }
+}
+
+impl<K, V> Debug for Cache<K, V> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Cache")
+      .field("total_size", &self.total_size)
+      .field("num_pages", &self.pages.len())
+      .finish()
+  }
+}
+
+
+/* Pages of blobs, indexed by url to invalidate one blob without a full scan */
+
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub struct PageKey {
+  pub url: Url,
+  pub page_idx: usize
+}
+
+impl Debug for PageKey {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PageKey")
+      .field("url", &self.url.to_string())
+      .field("page_idx", &self.page_idx)
+      .finish()
+  }
+}
+
+impl PageKey {
+  pub fn new(url: Url, page_idx: usize) -> PageKey {
+    PageKey { url, page_idx }
+  }
+
+  pub fn set_page(&mut self, page_idx: usize) {
+    self.page_idx = page_idx
+  }
+}
+
+pub struct PageCache<V> {
+  cache: Cache<PageKey, V>,
+  url_pages: HashMap<Url, BTreeSet<usize>>,  // url --> cached page indexes
+}
+
+impl<V> Debug for PageCache<V> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PageCache")
+      .field("cache", &self.cache)
+      .field("num_urls", &self.url_pages.len())
+      .finish()
+  }
+}
+
+impl<V> PageCache<V> {
+  pub fn new(total_page: usize, policy: CachePolicy) -> PageCache<V> {
+    PageCache { cache: Cache::new(total_page, policy), url_pages: HashMap::new() }
+  }
+
+  pub fn len(&self) -> usize {
+    self.cache.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.cache.is_empty()
+  }
+
+  pub fn get(&mut self, page_key: &PageKey) -> Option<&V> {
+    self.cache.get(page_key)
+  }
+
+  pub fn contains(&self, page_key: &PageKey) -> bool {
+    self.cache.contains(page_key)
+  }
+
+  // returns pages evicted to make room
+  pub fn put(&mut self, page_key: PageKey, value: V) -> Vec<PageKey> {
+    let url = page_key.url.clone();
+    let page_idx = page_key.page_idx;
+    let victims = self.cache.put(page_key, value);
+    for victim in &victims {
+      self.forget(victim);
+    }
+    if self.cache.contains(&PageKey::new(url.clone(), page_idx)) {
+      self.url_pages.entry(url).or_default().insert(page_idx);
+    }
+    victims
+  }
+
+  pub fn remove(&mut self, page_key: &PageKey) -> Option<V> {
+    let value = self.cache.remove(page_key);
+    self.forget(page_key);
+    value
+  }
+
+  fn forget(&mut self, page_key: &PageKey) {
+    if let Some(page_idxs) = self.url_pages.get_mut(&page_key.url) {
+      page_idxs.remove(&page_key.page_idx);
+      if page_idxs.is_empty() {
+        self.url_pages.remove(&page_key.url);
+      }
+    }
+  }
+
+  // drop all pages of the url, returns dropped pages
+  pub fn invalidate(&mut self, url: &Url) -> Vec<PageKey> {
+    let page_keys: Vec<PageKey> = match self.url_pages.remove(url) {
+      Some(page_idxs) => page_idxs.into_iter().map(|page_idx| PageKey::new(url.clone(), page_idx)).collect(),
+      None => return Vec::new(),
+    };
+    for page_key in &page_keys {
+      self.cache.remove(page_key);
+    }
+    page_keys
+  }
+
+  pub fn invalidate_prefix(&mut self, prefix: &Url) -> Vec<PageKey> {
+    let urls: Vec<Url> = self.url_pages.keys()
+      .filter(|url| url.as_str().starts_with(prefix.as_str()))
+      .cloned()
+      .collect();
+    urls.iter().flat_map(|url| self.invalidate(url)).collect()
+  }
+}
+
+
+/* Page cache split into independently locked shards, for concurrent readers */
+
+const MIN_PAGES_PER_SHARD: usize = 1 << 10;
+const MAX_SHARDS: usize = 16;
+
+pub struct ShardedPageCache<V> {
+  shards: Vec<Mutex<PageCache<V>>>,
+}
+
+impl<V: Clone> Debug for ShardedPageCache<V> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ShardedPageCache")
+      .field("num_shards", &self.shards.len())
+      .field("num_pages", &self.len())
+      .finish()
+  }
+}
+
+impl<V: Clone> ShardedPageCache<V> {
+  // small caches stay in one shard to keep the exact eviction order
+  pub fn new(total_page: usize, policy: CachePolicy) -> ShardedPageCache<V> {
+    let num_shards = (total_page / MIN_PAGES_PER_SHARD).clamp(1, MAX_SHARDS);
+    let shards = (0..num_shards)
+      .map(|shard_idx| {
+        let shard_page = total_page / num_shards + (shard_idx < total_page % num_shards) as usize;
+        Mutex::new(PageCache::new(shard_page, policy))
+      })
+      .collect();
+    ShardedPageCache { shards }
+  }
+
+  fn shard_of(&self, page_key: &PageKey) -> MutexGuard<'_, PageCache<V>> {
+    let mut hasher = DefaultHasher::new();
+    page_key.hash(&mut hasher);
+    let shard_idx = hasher.finish() as usize % self.shards.len();
+    self.shards[shard_idx].lock().unwrap()
+  }
+
+  pub fn len(&self) -> usize {
+    self.shards.iter().map(|shard| shard.lock().unwrap().len()).sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn get(&self, page_key: &PageKey) -> Option<V> {
+    self.shard_of(page_key).get(page_key).cloned()
+  }
+
+  pub fn contains(&self, page_key: &PageKey) -> bool {
+    self.shard_of(page_key).contains(page_key)
+  }
+
+  // returns pages evicted to make room
+  pub fn put(&self, page_key: PageKey, value: V) -> Vec<PageKey> {
+    self.shard_of(&page_key).put(page_key, value)
+  }
+
+  // drop all pages of the url, returns dropped pages
+  pub fn invalidate(&self, url: &Url) -> Vec<PageKey> {
+    self.shards.iter().flat_map(|shard| shard.lock().unwrap().invalidate(url)).collect()
+  }
+
+  pub fn invalidate_prefix(&self, prefix: &Url) -> Vec<PageKey> {
+    self.shards.iter().flat_map(|shard| shard.lock().unwrap().invalidate_prefix(prefix)).collect()
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn fill(cache: &mut Cache<usize, usize>, keys: &[usize]) {
+    for key in keys {
+      if cache.get(key).is_none() {
+        cache.put(*key, *key);
+      }
+    }
+  }
+
+  fn cached_keys(cache: &Cache<usize, usize>, max_key: usize) -> Vec<usize> {
+    (0..=max_key).filter(|key| cache.contains(key)).collect()
+  }
+
+  #[test]
+  fn fifo_evicts_oldest() {
+    let mut cache = Cache::new(3, CachePolicy::Fifo);
+    fill(&mut cache, &[0, 1, 2, 0, 3]);
+    assert_eq!(cached_keys(&cache, 3), vec![1, 2, 3]);
+    assert_eq!(cache.put(4, 4), vec![1]);
+    assert_eq!(cache.put(4, 40), Vec::<usize>::new());
+  }
+
+  #[test]
+  fn lru_evicts_least_recent() {
+    let mut cache = Cache::new(3, CachePolicy::Lru);
+    fill(&mut cache, &[0, 1, 2, 0, 3]);
+    assert_eq!(cached_keys(&cache, 3), vec![0, 2, 3]);
+  }
+
+  #[test]
+  fn clock_gives_second_chance() {
+    let mut cache = Cache::new(3, CachePolicy::Clock);
+    fill(&mut cache, &[0, 1, 2, 0, 3]);
+    assert_eq!(cached_keys(&cache, 3), vec![0, 2, 3]);
+    fill(&mut cache, &[4]);
+    assert_eq!(cached_keys(&cache, 4), vec![0, 3, 4]);
+  }
+
+  #[test]
+  fn twoq_keeps_hot_keys_over_scan() {
+    let mut cache = Cache::new(8, CachePolicy::TwoQ);
+
+    // hot keys, evicted once then re-referenced into the frequent queue
+    fill(&mut cache, &[0, 1]);
+    fill(&mut cache, &(100..108).collect::<Vec<usize>>());
+    fill(&mut cache, &[0, 1]);
+
+    // one-time scan should not push hot keys out
+    fill(&mut cache, &(200..300).collect::<Vec<usize>>());
+    assert!(cache.contains(&0) && cache.contains(&1));
+    assert_eq!(cache.len(), 8);
+  }
+
+  #[test]
+  fn remove_and_clear_ok() {
+    for policy in [CachePolicy::Fifo, CachePolicy::Lru, CachePolicy::Clock, CachePolicy::TwoQ] {
+      let mut cache = Cache::new(4, policy);
+      fill(&mut cache, &[0, 1, 2, 3]);
+      assert_eq!(cache.remove(&1), Some(1));
+      assert_eq!(cache.remove(&1), None);
+      fill(&mut cache, &[4, 5, 6, 7, 8, 9]);
+      assert_eq!(cache.len(), 4, "Exceeded capacity with {:?}", policy);
+      cache.put(9, 90);
+      assert_eq!(cache.get(&9), Some(&90));
+      cache.clear();
+      assert!(cache.is_empty());
+      fill(&mut cache, &[10, 11, 12, 13, 14]);
+      assert_eq!(cache.len(), 4, "Exceeded capacity after clear with {:?}", policy);
+    }
+  }
+
+  #[test]
+  fn zero_capacity_caches_nothing() {
+    let mut cache = Cache::new(0, CachePolicy::Lru);
+    fill(&mut cache, &[0, 1]);
+    assert!(cache.is_empty());
+  }
+
+  #[test]
+  fn page_cache_invalidate_ok() -> Result<(), GenericError> {
+    let url_a = Url::parse("mem:///layers/a")?;
+    let url_b = Url::parse("mem:///layers/b")?;
+    let url_c = Url::parse("mem:///data")?;
+    let mut cache = PageCache::new(6, CachePolicy::Fifo);
+    for (url, page_idx) in [(&url_a, 0), (&url_a, 1), (&url_b, 0), (&url_c, 0), (&url_c, 1), (&url_c, 2)] {
+      cache.put(PageKey::new(url.clone(), page_idx), page_idx);
+    }
+
+    // eviction and removal keep the url index in sync
+    assert_eq!(cache.put(PageKey::new(url_c.clone(), 3), 3), vec![PageKey::new(url_a.clone(), 0)]);
+    assert_eq!(cache.remove(&PageKey::new(url_c.clone(), 1)), Some(1));
+    assert_eq!(cache.invalidate(&url_c), vec![PageKey::new(url_c.clone(), 0), PageKey::new(url_c.clone(), 2), PageKey::new(url_c, 3)]);
+    assert_eq!(cache.invalidate_prefix(&Url::parse("mem:///layers/")?).len(), 2);
+    assert!(cache.is_empty());
+    assert!(cache.invalidate(&url_a).is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn sharded_page_cache_ok() -> Result<(), GenericError> {
+    let url = Url::parse("mem:///blob")?;
+    let cache = ShardedPageCache::new(4 * MIN_PAGES_PER_SHARD + 3, CachePolicy::Lru);
+    assert_eq!(cache.shards.len(), 4);
+    let shard_pages: Vec<usize> = cache.shards.iter().map(|shard| shard.lock().unwrap().cache.total_size).collect();
+    assert_eq!(shard_pages.iter().sum::<usize>(), 4 * MIN_PAGES_PER_SHARD + 3);
+
+    // concurrent puts never exceed the capacity
+    std::thread::scope(|scope| {
+      for thread_idx in 0..4 {
+        let cache = &cache;
+        let url = &url;
+        scope.spawn(move || {
+          for page_idx in 0..2 * MIN_PAGES_PER_SHARD {
+            cache.put(PageKey::new(url.clone(), thread_idx * 2 * MIN_PAGES_PER_SHARD + page_idx), page_idx);
+          }
+        });
+      }
+    });
+    assert_eq!(cache.len(), 4 * MIN_PAGES_PER_SHARD + 3);
+    assert_eq!(cache.invalidate(&url).len(), 4 * MIN_PAGES_PER_SHARD + 3);
+    assert!(cache.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn parse_policy() -> Result<(), GenericError> {
+    assert_eq!("lru".parse::<CachePolicy>()?, CachePolicy::Lru);
+    assert_eq!("2q".parse::<CachePolicy>()?, CachePolicy::TwoQ);
+    assert!("arc".parse::<CachePolicy>().is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,247 @@
+use rand::Rng;
+use rand::seq::SliceRandom;
+use serde::Serialize;
+use std::time::Instant;
+use url::Url;
+
+use crate::common::error::CalibrationError;
+use crate::common::error::GResult;
+use crate::io::profile::AffineStorageProfile;
+use crate::io::profile::Bandwidth;
+use crate::io::profile::Latency;
+use crate::io::profile::StorageProfile;
+use crate::io::storage::Adaptor;
+use crate::io::storage::Range;
+
+
+/* Fit an affine storage profile from timed reads against a live adaptor */
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CalibrationConfig {
+  pub min_read_size: usize,
+  pub max_read_size: usize,  // capped by blob size
+  pub size_multiplier: usize,  // between consecutive read sizes
+  pub reads_per_size: usize,
+}
+
+impl Default for CalibrationConfig {
+  fn default() -> Self {
+    CalibrationConfig {
+      min_read_size: 1 << 12,  // 4 KB
+      max_read_size: 1 << 24,  // 16 MB
+      size_multiplier: 4,
+      reads_per_size: 8,
+    }
+  }
+}
+
+impl CalibrationConfig {
+  fn read_sizes(&self, blob_size: usize) -> Vec<usize> {
+    let max_read_size = std::cmp::min(self.max_read_size, blob_size);
+    let mut read_sizes = Vec::new();
+    let mut read_size = self.min_read_size;
+    while read_size <= max_read_size {
+      read_sizes.push(read_size);
+      read_size *= self.size_multiplier;
+    }
+    read_sizes
+  }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReadSample {
+  pub read_size: usize,
+  pub offset: usize,
+  pub elapsed_ns: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Calibration {
+  pub profile: AffineStorageProfile,
+  pub r_squared: f64,  // of the fitted profile over all samples
+  pub median_relative_error: f64,
+  pub samples: Vec<ReadSample>,
+}
+
+// random reads within the first blob_size bytes of url, sizes shuffled to spread drifts
+pub fn calibrate(adaptor: &dyn Adaptor, url: &Url, blob_size: usize, config: &CalibrationConfig) -> GResult<Calibration> {
+  let origin = Instant::now();
+  calibrate_with_clock(adaptor, url, blob_size, config, &|| origin.elapsed().as_nanos() as u64)
+}
+
+// clock_ns returns monotonic nanoseconds, e.g. virtual time in tests
+fn calibrate_with_clock(
+  adaptor: &dyn Adaptor,
+  url: &Url,
+  blob_size: usize,
+  config: &CalibrationConfig,
+  clock_ns: &dyn Fn() -> u64,
+) -> GResult<Calibration> {
+  let read_sizes = config.read_sizes(blob_size);
+  if read_sizes.len() < 2 {
+    return Err(CalibrationError::boxed(url.to_string(), "blob too small for two distinct read sizes"));
+  }
+  let mut schedule: Vec<usize> = read_sizes.iter()
+    .flat_map(|read_size| std::iter::repeat_n(*read_size, config.reads_per_size))
+    .collect();
+  let mut rng = rand::thread_rng();
+  schedule.shuffle(&mut rng);
+
+  // first read warms up connections and is not measured
+  adaptor.read_range(url, &Range { offset: 0, length: read_sizes[0] })?;
+  let mut samples = Vec::with_capacity(schedule.len());
+  for read_size in schedule {
+    let offset = rng.gen_range(0..=blob_size - read_size);
+    let start_ns = clock_ns();
+    let buffer = adaptor.read_range(url, &Range { offset, length: read_size })?;
+    let elapsed_ns = clock_ns() - start_ns;
+    if buffer.len() != read_size {
+      return Err(CalibrationError::boxed(url.to_string(), "read beyond end of blob, blob_size too large"));
+    }
+    samples.push(ReadSample { read_size, offset, elapsed_ns });
+  }
+
+  let calibration = fit(samples);
+  log::info!(
+    "Calibrated {:?} with r^2= {:.4}, median relative error= {:.4}",
+    calibration.profile,
+    calibration.r_squared,
+    calibration.median_relative_error,
+  );
+  Ok(calibration)
+}
+
+// theil-sen estimator, insensitive to outliers like tail latencies
+fn fit(samples: Vec<ReadSample>) -> Calibration {
+  let mut slopes = Vec::new();
+  for (idx, sample_a) in samples.iter().enumerate() {
+    for sample_b in &samples[idx + 1..] {
+      if sample_a.read_size != sample_b.read_size {
+        let rise = sample_b.elapsed_ns as f64 - sample_a.elapsed_ns as f64;
+        let run = sample_b.read_size as f64 - sample_a.read_size as f64;
+        slopes.push(rise / run);
+      }
+    }
+  }
+  let nspb = median(&mut slopes).max(0.0);
+  let mut intercepts: Vec<f64> = samples.iter()
+    .map(|sample| sample.elapsed_ns as f64 - nspb * sample.read_size as f64)
+    .collect();
+  let latency_ns = median(&mut intercepts).max(0.0);
+  let profile = AffineStorageProfile::new(Latency::from_nanos(latency_ns as u64), Bandwidth { nspb });
+
+  // goodness of fit
+  let mean_ns = samples.iter().map(|sample| sample.elapsed_ns as f64).sum::<f64>() / samples.len() as f64;
+  let mut ss_res = 0.0;
+  let mut ss_tot = 0.0;
+  let mut relative_errors = Vec::with_capacity(samples.len());
+  for sample in &samples {
+    let actual_ns = sample.elapsed_ns as f64;
+    let predicted_ns = profile.cost(sample.read_size).as_nanos() as f64;
+    ss_res += (actual_ns - predicted_ns).powi(2);
+    ss_tot += (actual_ns - mean_ns).powi(2);
+    relative_errors.push((actual_ns - predicted_ns).abs() / actual_ns.max(1.0));
+  }
+  let r_squared = if ss_tot > 0.0 { 1.0 - ss_res / ss_tot } else { 1.0 };
+  Calibration {
+    profile,
+    r_squared,
+    median_relative_error: median(&mut relative_errors),
+    samples,
+  }
+}
+
+fn median(values: &mut [f64]) -> f64 {
+  values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+  let mid = values.len() / 2;
+  if values.len() % 2 == 1 {
+    values[mid]
+  } else {
+    (values[mid - 1] + values[mid]) / 2.0
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::AtomicU64;
+  use std::sync::atomic::Ordering;
+
+  use crate::common::SharedBytes;
+  use crate::io::storage::MemoryAdaptor;
+
+  fn sample(read_size: usize, elapsed_ns: u64) -> ReadSample {
+    ReadSample { read_size, offset: 0, elapsed_ns }
+  }
+
+  #[test]
+  fn fit_ignores_outliers_ok() {
+    // 1 ms + 10 ns/byte, with a few tail latencies
+    let mut samples: Vec<ReadSample> = (1..=20)
+      .map(|idx| sample(idx * 1000, 1_000_000 + 10 * idx as u64 * 1000))
+      .collect();
+    samples[3].elapsed_ns *= 20;
+    samples[11].elapsed_ns *= 50;
+    let calibration = fit(samples);
+    assert_eq!(calibration.profile, AffineStorageProfile::new(Latency::from_millis(1), Bandwidth { nspb: 10.0 }));
+    assert_eq!(calibration.median_relative_error, 0.0);
+    assert!(calibration.r_squared < 1.0);
+  }
+
+  // advances a virtual clock by the profile cost of each read instead of sleeping
+  #[derive(Debug)]
+  struct VirtualTimeAdaptor {
+    inner: MemoryAdaptor,
+    profile: AffineStorageProfile,
+    now_ns: AtomicU64,
+  }
+
+  impl Adaptor for VirtualTimeAdaptor {
+    fn read_all(&self, url: &Url) -> GResult<SharedBytes> {
+      self.inner.read_all(url)
+    }
+
+    fn read_range(&self, url: &Url, range: &Range) -> GResult<SharedBytes> {
+      self.now_ns.fetch_add(self.profile.cost(range.length).as_nanos() as u64, Ordering::Relaxed);
+      self.inner.read_range(url, range)
+    }
+
+    fn read_in_place(&self, url: &Url, range: &Range, buffer: &mut [u8]) -> GResult<()> {
+      self.inner.read_in_place(url, range, buffer)
+    }
+
+    fn create(&self, url: &Url) -> GResult<()> {
+      self.inner.create(url)
+    }
+
+    fn write_all(&self, url: &Url, buf: &[u8]) -> GResult<()> {
+      self.inner.write_all(url, buf)
+    }
+
+    fn remove(&self, url: &Url) -> GResult<()> {
+      self.inner.remove(url)
+    }
+  }
+
+  #[test]
+  fn calibrate_virtual_time_ok() -> GResult<()> {
+    let url = Url::parse("mem:///blob")?;
+    let expected = AffineStorageProfile::new(Latency::from_millis(1), Bandwidth::from_mbps(100.0));
+    let vta = VirtualTimeAdaptor { inner: MemoryAdaptor::new(), profile: expected.clone(), now_ns: AtomicU64::new(0) };
+    vta.write_all(&url, &vec![0u8; 1 << 20])?;
+    let clock_ns = || vta.now_ns.load(Ordering::Relaxed);
+    let config = CalibrationConfig { min_read_size: 1 << 12, max_read_size: 1 << 24, size_multiplier: 4, reads_per_size: 4 };
+    let calibration = calibrate_with_clock(&vta, &url, 1 << 20, &config, &clock_ns)?;
+    assert_eq!(calibration.samples.len(), 5 * 4);
+    assert_eq!(calibration.profile, expected);
+    assert_eq!(calibration.r_squared, 1.0);
+    assert_eq!(calibration.median_relative_error, 0.0);
+
+    // too small for two read sizes
+    assert!(calibrate_with_clock(&vta, &url, 1 << 13, &config, &clock_ns).is_err());
+    // blob shorter than claimed, largest read comes back short
+    assert!(calibrate_with_clock(&vta, &url, 1 << 22, &config, &clock_ns).is_err());
+    Ok(())
+  }
+}
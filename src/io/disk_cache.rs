@@ -0,0 +1,341 @@
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use url::Url;
+
+use crate::common::SharedByteSlice;
+use crate::common::SharedBytes;
+use crate::common::error::GResult;
+use crate::io::cache::CachePolicy;
+use crate::io::cache::PageCache;
+use crate::io::cache::PageKey;
+
+
+/* Local disk tier behind the in-memory page cache, pages survive restarts */
+
+// layout: <dir>/page_<page_size>/<sha256 of url>/{meta.json, <page_idx>.page}
+
+const META_FILE: &str = "meta.json";
+const PAGE_EXTENSION: &str = "page";
+const TEMP_EXTENSION: &str = "tmp";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct BlobMeta {
+  url: String,
+  version: String,  // blob version when its pages were cached
+}
+
+pub struct DiskCache {
+  root: PathBuf,
+  page_size: usize,
+  pages: PageCache<()>,
+  versions: HashMap<Url, String>,  // blobs with directory on disk
+  validated: HashMap<Url, bool>,  // checked against the adaptor in this process, whether usable
+}
+
+impl std::fmt::Debug for DiskCache {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("DiskCache")
+      .field("root", &self.root)
+      .field("page_size", &self.page_size)
+      .field("pages", &self.pages)
+      .field("num_blobs", &self.versions.len())
+      .finish()
+  }
+}
+
+impl DiskCache {
+  // cache_size in bytes, reloads pages cached by previous processes
+  pub fn open(dir: &Path, cache_size: usize, page_size: usize, policy: CachePolicy) -> GResult<DiskCache> {
+    let root = dir.join(format!("page_{}", page_size));
+    std::fs::create_dir_all(&root)?;
+    let mut disk_cache = DiskCache {
+      root,
+      page_size,
+      pages: PageCache::new(cache_size / page_size, policy),
+      versions: HashMap::new(),
+      validated: HashMap::new(),
+    };
+    disk_cache.reload()?;
+    Ok(disk_cache)
+  }
+
+  fn reload(&mut self) -> GResult<()> {
+    // oldest pages first so that eviction order roughly carries over
+    let mut page_files: Vec<(SystemTime, PageKey)> = Vec::new();
+    for blob_entry in std::fs::read_dir(&self.root)? {
+      let blob_dir = blob_entry?.path();
+      let meta: BlobMeta = match File::open(blob_dir.join(META_FILE)) {
+        Ok(meta_file) => match serde_json::from_reader(meta_file) {
+          Ok(meta) => meta,
+          Err(e) => {
+            // unreadable meta, drop its pages rather than failing to open
+            log::warn!("Dropping disk cache at {:?} with corrupt meta, {}", blob_dir, e);
+            std::fs::remove_dir_all(&blob_dir)?;
+            continue;
+          },
+        },
+        Err(_) => {
+          // interrupted before writing meta, pages cannot be validated
+          std::fs::remove_dir_all(&blob_dir)?;
+          continue;
+        },
+      };
+      let url = Url::parse(&meta.url)?;
+      for page_entry in std::fs::read_dir(&blob_dir)? {
+        let page_path = page_entry?.path();
+        match page_path.extension().and_then(|extension| extension.to_str()) {
+          Some(PAGE_EXTENSION) => {
+            let page_idx = page_path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok());
+            match page_idx {
+              Some(page_idx) => page_files.push((page_path.metadata()?.modified()?, PageKey::new(url.clone(), page_idx))),
+              None => std::fs::remove_file(&page_path)?,
+            }
+          },
+          Some(TEMP_EXTENSION) => std::fs::remove_file(&page_path)?,
+          _ => (),
+        }
+      }
+      self.versions.insert(url, meta.version);
+    }
+    page_files.sort_by_key(|(modified, _)| *modified);
+    for (_, page_key) in page_files {
+      self.put_key(page_key)?;
+    }
+    log::info!("Reloaded disk cache at {:?} with {} pages", self.root, self.pages.len());
+    Ok(())
+  }
+
+  pub fn page_size(&self) -> usize {
+    self.page_size
+  }
+
+  pub fn len(&self) -> usize {
+    self.pages.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.pages.is_empty()
+  }
+
+  // whether validate was called for this url in this process, and its result
+  pub fn validated(&self, url: &Url) -> Option<bool> {
+    self.validated.get(url).copied()
+  }
+
+  // drop pages cached under another version, none version disables caching for the url
+  pub fn validate(&mut self, url: &Url, version: Option<String>) -> GResult<bool> {
+    let is_usable = version.is_some();
+    match version {
+      Some(version) if self.versions.get(url) == Some(&version) => (),
+      Some(version) => {
+        self.invalidate(url)?;
+        let blob_dir = self.blob_dir(url);
+        std::fs::create_dir_all(&blob_dir)?;
+        let meta = BlobMeta { url: url.to_string(), version: version.clone() };
+        // write then rename like pages, so that a crash never leaves a partial meta
+        let meta_path = blob_dir.join(META_FILE);
+        let temp_path = meta_path.with_extension(TEMP_EXTENSION);
+        serde_json::to_writer(File::create(&temp_path)?, &meta)?;
+        std::fs::rename(&temp_path, &meta_path)?;
+        self.versions.insert(url.clone(), version);
+      },
+      None => {
+        self.invalidate(url)?;
+      },
+    }
+    self.validated.insert(url.clone(), is_usable);
+    Ok(is_usable)
+  }
+
+  pub fn get(&mut self, page_key: &PageKey) -> GResult<Option<SharedByteSlice>> {
+    if self.pages.get(page_key).is_none() {
+      return Ok(None);
+    }
+    match std::fs::read(self.page_path(page_key)) {
+      Ok(page_bytes) => Ok(Some(SharedBytes::from(page_bytes).slice_all())),
+      Err(e) if e.kind() == ErrorKind::NotFound => {
+        // removed behind our back
+        self.pages.remove(page_key);
+        Ok(None)
+      },
+      Err(e) => Err(Box::new(e)),
+    }
+  }
+
+  // only pages of validated blobs are kept
+  pub fn put(&mut self, page_key: PageKey, page_bytes: &[u8]) -> GResult<()> {
+    if self.validated(&page_key.url) != Some(true) || self.pages.contains(&page_key) {
+      return Ok(());
+    }
+
+    // write then rename so that a crash never leaves a partial page
+    let page_path = self.page_path(&page_key);
+    let temp_path = page_path.with_extension(TEMP_EXTENSION);
+    std::fs::write(&temp_path, page_bytes)?;
+    std::fs::rename(&temp_path, &page_path)?;
+    self.put_key(page_key)
+  }
+
+  fn put_key(&mut self, page_key: PageKey) -> GResult<()> {
+    for victim in self.pages.put(page_key.clone(), ()) {
+      self.remove_page_file(&victim)?;
+    }
+    if !self.pages.contains(&page_key) {
+      // not admitted, e.g. zero capacity
+      self.remove_page_file(&page_key)?;
+    }
+    Ok(())
+  }
+
+  // drop all pages of the url, returns number of pages dropped
+  pub fn invalidate(&mut self, url: &Url) -> GResult<usize> {
+    let num_pages = self.pages.invalidate(url).len();
+    self.validated.remove(url);
+    if self.versions.remove(url).is_some() {
+      std::fs::remove_dir_all(self.blob_dir(url))?;
+    }
+    Ok(num_pages)
+  }
+
+  pub fn invalidate_prefix(&mut self, prefix: &Url) -> GResult<usize> {
+    let urls: Vec<Url> = self.versions.keys()
+      .filter(|url| url.as_str().starts_with(prefix.as_str()))
+      .cloned()
+      .collect();
+    let mut num_pages = 0;
+    for url in urls {
+      num_pages += self.invalidate(&url)?;
+    }
+    Ok(num_pages)
+  }
+
+  fn remove_page_file(&self, page_key: &PageKey) -> GResult<()> {
+    match std::fs::remove_file(self.page_path(page_key)) {
+      Err(e) if e.kind() != ErrorKind::NotFound => Err(Box::new(e)),
+      _ => Ok(()),
+    }
+  }
+
+  fn blob_dir(&self, url: &Url) -> PathBuf {
+    self.root.join(hex::encode(Sha256::digest(url.as_str().as_bytes())))
+  }
+
+  fn page_path(&self, page_key: &PageKey) -> PathBuf {
+    self.blob_dir(&page_key.url).join(format!("{}.{}", page_key.page_idx, PAGE_EXTENSION))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  fn page_of(disk_cache: &mut DiskCache, url: &Url, page_idx: usize) -> GResult<Option<Vec<u8>>> {
+    Ok(disk_cache.get(&PageKey::new(url.clone(), page_idx))?.map(|page| page[..].to_vec()))
+  }
+
+  #[test]
+  fn persist_across_reopen_ok() -> GResult<()> {
+    let temp_dir = TempDir::new()?;
+    let url = Url::parse("az:///container/blob")?;
+    {
+      let mut disk_cache = DiskCache::open(temp_dir.path(), 1000, 100, CachePolicy::Lru)?;
+      assert!(disk_cache.validate(&url, Some("v1".to_string()))?);
+      disk_cache.put(PageKey::new(url.clone(), 0), &[1u8; 100])?;
+      disk_cache.put(PageKey::new(url.clone(), 3), &[2u8; 40])?;
+    }
+
+    // same version, pages still there
+    let mut disk_cache = DiskCache::open(temp_dir.path(), 1000, 100, CachePolicy::Lru)?;
+    assert_eq!(disk_cache.len(), 2);
+    assert_eq!(disk_cache.validated(&url), None);
+    disk_cache.validate(&url, Some("v1".to_string()))?;
+    assert_eq!(page_of(&mut disk_cache, &url, 0)?, Some(vec![1u8; 100]));
+    assert_eq!(page_of(&mut disk_cache, &url, 3)?, Some(vec![2u8; 40]));
+    assert_eq!(page_of(&mut disk_cache, &url, 1)?, None);
+
+    // other page size does not see these pages
+    let disk_cache_other = DiskCache::open(temp_dir.path(), 1000, 200, CachePolicy::Lru)?;
+    assert!(disk_cache_other.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn corrupt_meta_dropped_ok() -> GResult<()> {
+    let temp_dir = TempDir::new()?;
+    let url = Url::parse("az:///container/blob")?;
+    let other_url = Url::parse("az:///container/other_blob")?;
+    let blob_dir = {
+      let mut disk_cache = DiskCache::open(temp_dir.path(), 1000, 100, CachePolicy::Lru)?;
+      for url in [&url, &other_url] {
+        disk_cache.validate(url, Some("v1".to_string()))?;
+        disk_cache.put(PageKey::new(url.clone(), 0), &[1u8; 100])?;
+      }
+      disk_cache.blob_dir(&url)
+    };
+
+    // meta cut short by a crash
+    std::fs::write(blob_dir.join(META_FILE), b"{\"url\": \"az:")?;
+    let mut disk_cache = DiskCache::open(temp_dir.path(), 1000, 100, CachePolicy::Lru)?;
+    assert_eq!(disk_cache.len(), 1);
+    assert!(!blob_dir.exists());
+    disk_cache.validate(&other_url, Some("v1".to_string()))?;
+    assert_eq!(page_of(&mut disk_cache, &other_url, 0)?, Some(vec![1u8; 100]));
+    Ok(())
+  }
+
+  #[test]
+  fn validate_drops_stale_version_ok() -> GResult<()> {
+    let temp_dir = TempDir::new()?;
+    let url = Url::parse("s3://bucket/blob")?;
+    {
+      let mut disk_cache = DiskCache::open(temp_dir.path(), 1000, 100, CachePolicy::Fifo)?;
+      disk_cache.validate(&url, Some("\"etag-1\"".to_string()))?;
+      disk_cache.put(PageKey::new(url.clone(), 0), &[1u8; 100])?;
+    }
+    let mut disk_cache = DiskCache::open(temp_dir.path(), 1000, 100, CachePolicy::Fifo)?;
+    disk_cache.validate(&url, Some("\"etag-2\"".to_string()))?;
+    assert_eq!(page_of(&mut disk_cache, &url, 0)?, None);
+
+    // unknown version never caches
+    assert!(!disk_cache.validate(&url, None)?);
+    disk_cache.put(PageKey::new(url.clone(), 0), &[1u8; 100])?;
+    assert!(disk_cache.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn evict_and_invalidate_ok() -> GResult<()> {
+    let temp_dir = TempDir::new()?;
+    let url_a = Url::parse("https://host/layers/a")?;
+    let url_b = Url::parse("https://host/layers/b")?;
+    let mut disk_cache = DiskCache::open(temp_dir.path(), 300, 100, CachePolicy::Fifo)?;
+    disk_cache.validate(&url_a, Some("1".to_string()))?;
+    disk_cache.validate(&url_b, Some("1".to_string()))?;
+    for page_idx in 0..3 {
+      disk_cache.put(PageKey::new(url_a.clone(), page_idx), &[page_idx as u8; 100])?;
+    }
+    disk_cache.put(PageKey::new(url_b.clone(), 0), &[9u8; 100])?;
+    assert_eq!(disk_cache.len(), 3);
+    assert_eq!(page_of(&mut disk_cache, &url_a, 0)?, None);
+    assert!(!disk_cache.page_path(&PageKey::new(url_a.clone(), 0)).exists());
+
+    // invalidation removes files and requires revalidation
+    assert_eq!(disk_cache.invalidate_prefix(&Url::parse("https://host/layers/")?)?, 3);
+    assert!(disk_cache.is_empty());
+    assert_eq!(disk_cache.validated(&url_a), None);
+    assert!(!disk_cache.blob_dir(&url_a).exists());
+    drop(disk_cache);
+    assert!(DiskCache::open(temp_dir.path(), 300, 100, CachePolicy::Fifo)?.is_empty());
+    Ok(())
+  }
+}
@@ -1,9 +1,12 @@
-// use lru::LruCache;
-use std::cell::RefCell;
+use itertools::izip;
+use serde::Serialize;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::collections::VecDeque;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
 use url::Url;
 
 use crate::common::SharedBytes;
@@ -12,84 +15,123 @@ use crate::common::SharedByteView;
 use crate::common::error::ConflictingStorageScheme;
 use crate::common::error::GResult;
 use crate::common::error::UnavailableStorageScheme;
+use crate::io::cache::CachePolicy;
+use crate::io::cache::PageKey;
+use crate::io::cache::ShardedPageCache;
+use crate::io::disk_cache::DiskCache;
+use crate::io::intervals::Intervals;
+use crate::io::profile::StorageProfile;
 use crate::io::storage::Adaptor;
 use crate::io::storage::Range;
+use crate::io::storage::ReadRequest;
+use crate::io::trace::SharedTraceRecorder;
+
+
+/* Cache statistics */
+
+#[derive(Serialize, Default, Clone, Debug, PartialEq)]
+pub struct CacheCounters {
+  pub hits: usize,  // requests served entirely from cache
+  pub misses: usize,  // requests that fetched from adaptors
+  pub hit_bytes: usize,  // requested bytes served from cached pages
+  pub fetched_bytes: usize,  // bytes read from adaptors, including page alignment
+  pub fetches: usize,  // reads issued to adaptors, i.e. billed requests on remote storage
+  pub evictions: usize,  // pages evicted, attributed to the evicted url
+  pub prepare_fallbacks: usize,  // pages evicted between prepare and collect, then read directly
+  pub disk_pages: usize,  // pages loaded from the disk tier instead of adaptors
+}
 
-
-/* In-memory cache */
-
-#[derive(Eq, PartialEq, Ord, PartialOrd)]
-struct PageKey {
-  pub url: Url,
-  pub page_idx: usize
+#[derive(Serialize, Default, Clone, Debug, PartialEq)]
+pub struct CacheStats {
+  pub total: CacheCounters,
+  pub by_scheme: BTreeMap<String, CacheCounters>,
+  pub by_prefix: BTreeMap<String, CacheCounters>,  // only tracked prefixes
 }
 
-impl std::fmt::Debug for PageKey {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    f.debug_struct("PageKey")
-      .field("url", &self.url.to_string())
-      .field("page_idx", &self.page_idx)
-      .finish()
-  }
+// counted without locks, requests from all threads go through the same counters
+#[derive(Default)]
+struct AtomicCacheCounters {
+  hits: AtomicUsize,
+  misses: AtomicUsize,
+  hit_bytes: AtomicUsize,
+  fetched_bytes: AtomicUsize,
+  fetches: AtomicUsize,
+  evictions: AtomicUsize,
+  prepare_fallbacks: AtomicUsize,
+  disk_pages: AtomicUsize,
 }
 
-impl PageKey {
-  fn new(url: Url, page_idx: usize) -> PageKey {
-    PageKey { url, page_idx }
+impl AtomicCacheCounters {
+  fn fields(&self) -> [&AtomicUsize; 8] {
+    [
+      &self.hits,
+      &self.misses,
+      &self.hit_bytes,
+      &self.fetched_bytes,
+      &self.fetches,
+      &self.evictions,
+      &self.prepare_fallbacks,
+      &self.disk_pages,
+    ]
   }
 
-  fn set_page(&mut self, page_idx: usize) {
-    self.page_idx = page_idx
+  fn snapshot(&self) -> CacheCounters {
+    let [hits, misses, hit_bytes, fetched_bytes, fetches, evictions, prepare_fallbacks, disk_pages] = self.fields()
+      .map(|counter| counter.load(Ordering::Relaxed));
+    CacheCounters { hits, misses, hit_bytes, fetched_bytes, fetches, evictions, prepare_fallbacks, disk_pages }
   }
-}
-
-struct KeyRef<K> {
-  k: *const K,
-}
 
-impl<K> KeyRef<K> {
-  fn borrow(&self) -> &K {
-    unsafe { &*self.k }
+  fn reset(&self) {
+    for counter in self.fields() {
+      counter.store(0, Ordering::Relaxed);
+    }
   }
 }
 
+fn add(counter: &AtomicUsize, value: usize) {
+  counter.fetch_add(value, Ordering::Relaxed);
+}
 
-struct Cache<K, V> {
-  total_size: usize,
-  pages: BTreeMap<K, V>,
-  fifo: VecDeque<KeyRef<K>>,
+// schemes and prefixes are fixed while setting up the storage, only counters change later
+#[derive(Default)]
+struct SharedCacheStats {
+  total: AtomicCacheCounters,
+  by_scheme: HashMap<String, AtomicCacheCounters>,
+  by_prefix: Vec<(String, AtomicCacheCounters)>,
 }
 
-impl<K: Ord, V> Cache<K, V> {
-  fn new(total_size: usize) -> Cache<K, V> {
-    Cache {
-      total_size,
-      pages: BTreeMap::new(),
-      fifo: VecDeque::with_capacity(total_size),
+impl SharedCacheStats {
+  fn count<F: Fn(&AtomicCacheCounters)>(&self, url: &Url, update: F) {
+    update(&self.total);
+    if let Some(counters) = self.by_scheme.get(url.scheme()) {
+      update(counters);
+    }
+    for (prefix, counters) in &self.by_prefix {
+      if url.as_str().starts_with(prefix.as_str()) {
+        update(counters);
+      }
     }
   }
 
-  fn get(&self, key: &K) -> Option<&V> {
-    self.pages.get(key)
-  }
-
-  fn contains(&self, key: &K) -> bool {
-    self.pages.contains_key(key)
-  }
-
-  fn put(&mut self, key: K, value: V) {
-    if self.fifo.len() >= self.total_size {
-      if let Some(pop_key) = self.fifo.pop_front() {
-        self.pages.remove(pop_key.borrow());
-      }
+  // schemes without any request since the last reset are left out
+  fn snapshot(&self) -> CacheStats {
+    CacheStats {
+      total: self.total.snapshot(),
+      by_scheme: self.by_scheme.iter()
+        .map(|(scheme, counters)| (scheme.clone(), counters.snapshot()))
+        .filter(|(_, counters)| *counters != CacheCounters::default())
+        .collect(),
+      by_prefix: self.by_prefix.iter()
+        .map(|(prefix, counters)| (prefix.clone(), counters.snapshot()))
+        .collect(),
     }
-    self.fifo.push_back(KeyRef { k: &key });
-    self.pages.insert(key, value);
   }
 
-  fn clear(&mut self) {
-    self.fifo.clear();
-    self.pages.clear();
+  fn reset(&self) {
+    self.total.reset();
+    for counters in self.by_scheme.values().chain(self.by_prefix.iter().map(|(_, counters)| counters)) {
+      counters.reset();
+    }
   }
 }
 
@@ -97,12 +139,16 @@ impl<K: Ord, V> Cache<K, V> {
 /* Common io interface */
 
 pub struct ExternalStorage {
-  adaptors: HashMap<String, Rc<Box<dyn Adaptor>>>,
+  adaptors: HashMap<String, Arc<Box<dyn Adaptor>>>,
   schemes: Vec<String>,  // HACK: for error reporting
-  // page_cache: RefCell<LruCache<PageKey, SharedByteSlice>>,
-  page_cache: RefCell<Cache<PageKey, SharedByteSlice>>,
+  page_cache: ShardedPageCache<SharedByteSlice>,
   page_size: usize,
   total_page: usize,
+  recorder: Option<SharedTraceRecorder>,  // trace cache hits, misses are traced by adaptors
+  stats: SharedCacheStats,
+  disk_cache: Option<Mutex<DiskCache>>,  // second tier behind page_cache
+  disk_schemes: Vec<String>,  // schemes going through disk_cache
+  profile: Option<Box<dyn StorageProfile>>,  // to decide whether to fetch across cached pages
 }
 
 impl std::fmt::Debug for ExternalStorage {
@@ -112,6 +158,10 @@ impl std::fmt::Debug for ExternalStorage {
       .field("schemes", &self.schemes)
       .field("page_size", &self.page_size)
       .field("total_page", &self.total_page)
+      .field("page_cache", &self.page_cache)
+      .field("disk_cache", &self.disk_cache)
+      .field("disk_schemes", &self.disk_schemes)
+      .field("profile", &self.profile)
       .finish()
   }
 }
@@ -126,22 +176,63 @@ impl ExternalStorage {
   pub fn new() -> ExternalStorage {
     // ExternalStorage::new_with_cache(1 << 33 /* 8 GB */, 1 << 12 /* 1024 */)
     // ExternalStorage::new_with_cache(1 << 33 /* 8 GB */, 1 << 12 /* 2048 */)
-    ExternalStorage::new_with_cache(1 << 33 /* 8 GB */, 1 << 12 /* 4096 */)
+    ExternalStorage::new_with_cache(1 << 33 /* 8 GB */, 1 << 12 /* 4096 */, CachePolicy::default())
     // ExternalStorage::new_with_cache(1 << 33 /* 8 GB */, 1 << 13 /* 8192 */)
   }
 
-  pub fn new_with_cache(cache_size: usize, page_size: usize) -> ExternalStorage {
+  pub fn new_with_cache(cache_size: usize, page_size: usize, policy: CachePolicy) -> ExternalStorage {
     let total_page = cache_size / page_size;
     ExternalStorage{
       adaptors: HashMap::new(),
       schemes: Vec::new(),
-      // page_cache: RefCell::new(LruCache::new(total_page)),
-      page_cache: RefCell::new(Cache::new(total_page)),
+      page_cache: ShardedPageCache::new(total_page, policy),
       page_size,
       total_page,
+      recorder: None,
+      stats: SharedCacheStats::default(),
+      disk_cache: None,
+      disk_schemes: Vec::new(),
+      profile: None,
+    }
+  }
+
+  // bytes the in-memory page cache can hold
+  pub fn cache_capacity(&self) -> usize {
+    self.total_page * self.page_size
+  }
+
+  pub fn set_trace_recorder(&mut self, recorder: SharedTraceRecorder) {
+    self.recorder = Some(recorder);
+  }
+
+  // keep pages of these schemes (typically remote ones) also on local disk
+  pub fn set_disk_cache(&mut self, disk_cache: DiskCache, schemes: Vec<String>) {
+    assert_eq!(disk_cache.page_size(), self.page_size);
+    self.disk_cache = Some(Mutex::new(disk_cache));
+    self.disk_schemes = schemes;
+  }
+
+  // without a profile, missing pages are fetched as separate runs around cached ones
+  pub fn set_profile(&mut self, profile: Box<dyn StorageProfile>) {
+    self.profile = Some(profile);
+  }
+
+  // additionally count cache statistics of urls under this prefix
+  pub fn track_prefix(&mut self, prefix: &Url) {
+    let prefix = prefix.to_string();
+    if self.stats.by_prefix.iter().all(|(tracked_prefix, _)| *tracked_prefix != prefix) {
+      self.stats.by_prefix.push((prefix, AtomicCacheCounters::default()));
     }
   }
 
+  pub fn stats(&self) -> CacheStats {
+    self.stats.snapshot()
+  }
+
+  pub fn reset_stats(&self) {
+    self.stats.reset();
+  }
+
   pub fn with(mut self, scheme: String, adaptor: Box<dyn Adaptor>) -> GResult<Self> {
     self.register(scheme, adaptor)?;
     Ok(self)
@@ -154,12 +245,13 @@ impl ExternalStorage {
     }
 
     // new scheme
-    self.adaptors.insert(scheme.clone(), Rc::new(adaptor));
+    self.adaptors.insert(scheme.clone(), Arc::new(adaptor));
+    self.stats.by_scheme.insert(scheme.clone(), AtomicCacheCounters::default());
     self.schemes.push(scheme);
     Ok(())
   }
 
-  fn select_adaptor(&self, url: &Url) -> GResult<Rc<Box<dyn Adaptor>>> {
+  pub fn select_adaptor(&self, url: &Url) -> GResult<Arc<Box<dyn Adaptor>>> {
     let scheme = url.scheme();
     match self.adaptors.get(scheme) {
       Some(entry) => Ok(entry.clone()),
@@ -177,66 +269,204 @@ impl ExternalStorage {
 
   fn warm_cache_at(&self, url: &Url, buffer: &SharedByteSlice, offset: usize) {
     assert!(url.query().is_none());
+    self.buffer_to_pages(buffer, offset)
+      // .into_par_iter()
+      .for_each(|(page_idx, page_bytes)| {
+        self.put_page(PageKey::new(url.clone(), page_idx), page_bytes);
+      });
+  }
+
+  fn put_page(&self, page_key: PageKey, page_bytes: SharedByteSlice) {
+    let victims = self.page_cache.put(
+      page_key,
+      page_bytes,
+    );
+    for victim in victims {
+      self.stats.count(&victim.url, |counters| add(&counters.evictions, 1));
+    }
+  }
+
+  fn buffer_to_pages<'a>(&'a self, buffer: &'a SharedByteSlice, offset: usize) -> impl Iterator<Item = (usize, SharedByteSlice)> + 'a {
     assert_eq!(offset % self.page_size, 0);
     let length = buffer.len();
     let buffer_range = Range { offset, length };
     self.range_to_pages(&buffer_range)
-      // .into_par_iter()
-      .for_each(|page_idx| {
-        let page_key = PageKey::new(url.clone(), page_idx);
+      .map(move |page_idx| {
         let page_range = self.page_to_range(page_idx);
         let offset_l = page_range.offset - offset;  // underflow if offset not align
         let offset_r = std::cmp::min(length, page_range.offset + page_range.length - offset);
-        let page_bytes = buffer.slice(offset_l, offset_r - offset_l);
-        self.page_cache.borrow_mut().put(
-          page_key,
-          page_bytes,
-        );
-      });
+        (page_idx, buffer.slice(offset_l, offset_r - offset_l))
+      })
   }
 
-  fn prepare_cache(&self, page_key: &mut PageKey, range: &Range) -> GResult<()> {
-    if let Some(missing_range) = self.missing_cache_range(page_key, range) {
-      let cache_bytes = self.read_range_raw(page_key, &missing_range)?;
+  fn prepare_cache(&self, page_key: &mut PageKey, range: &Range) -> GResult<Vec<Range>> {  // fetched ranges, empty if all pages hit
+    self.load_disk_pages(page_key, range)?;
+    let missing_ranges = self.missing_cache_ranges(page_key, range);
+    let cache_bytes = match missing_ranges.len() {
+      0 => return Ok(missing_ranges),
+      1 => vec![self.read_range_raw(page_key, &missing_ranges[0])?],
+      _ => {
+        let requests: Vec<ReadRequest> = missing_ranges.iter()
+          .map(|missing_range| ReadRequest::Range { url: page_key.url.clone(), range: missing_range.clone() })
+          .collect();
+        self.read_batch_raw(&requests)?.iter().map(|bytes| bytes.slice_all()).collect()
+      },
+    };
+    for (missing_range, cache_bytes) in missing_ranges.iter().zip(cache_bytes) {
       log::trace!("Read missing cache of length {} bytes", cache_bytes.len());
       self.warm_cache_at(&page_key.url, &cache_bytes, missing_range.offset);
-      log::trace!("Warmed up missing cache");
+      self.persist_pages(&page_key.url, &cache_bytes, missing_range.offset)?;
+    }
+    log::trace!("Warmed up missing cache in {} reads", missing_ranges.len());
+    Ok(missing_ranges)
+  }
+
+  // whether the url goes through the disk tier, validated against the adaptor once per process
+  fn use_disk(&self, url: &Url) -> GResult<bool> {
+    let disk_cache = match &self.disk_cache {
+      Some(disk_cache) if self.disk_schemes.iter().any(|scheme| scheme == url.scheme()) => disk_cache,
+      _ => return Ok(false),
+    };
+    if let Some(is_usable) = disk_cache.lock().unwrap().validated(url) {
+      return Ok(is_usable);
+    }
+    let version = self.select_adaptor(url)?.version(url)?;
+    log::debug!("Validating disk cache of {} at version {:?}", url, version);
+    disk_cache.lock().unwrap().validate(url, version)
+  }
+
+  // move pages missing in memory but available on disk into memory
+  fn load_disk_pages(&self, page_key: &mut PageKey, range: &Range) -> GResult<()> {
+    if !self.use_disk(&page_key.url)? {
+      return Ok(());
+    }
+    let disk_cache = self.disk_cache.as_ref().unwrap();
+    for page_idx in self.range_to_pages(range) {
+      page_key.set_page(page_idx);
+      if !self.miss_cache(page_key) {
+        continue;
+      }
+      let page_bytes = disk_cache.lock().unwrap().get(page_key)?;
+      if let Some(page_bytes) = page_bytes {
+        self.put_page(page_key.clone(), page_bytes);
+        self.stats.count(&page_key.url, |counters| add(&counters.disk_pages, 1));
+      }
     }
     Ok(())
   }
 
-  fn missing_cache_range(&self, page_key: &mut PageKey, range: &Range) -> Option<Range> {
-    let mut missing_iter = self.range_to_pages(range)
-      .filter(|page_idx| {
-        page_key.set_page(*page_idx);
-        self.miss_cache(page_key)
-      });
-    if let Some(missing_left) = missing_iter.next() {
-      let missing_right = match missing_iter.rev().next() {
-        Some(missing) => missing,
-        None => missing_left,
+  fn persist_pages(&self, url: &Url, buffer: &SharedByteSlice, offset: usize) -> GResult<()> {
+    if !self.use_disk(url)? {
+      return Ok(());
+    }
+    let mut disk_cache = self.disk_cache.as_ref().unwrap().lock().unwrap();
+    for (page_idx, page_bytes) in self.buffer_to_pages(buffer, offset) {
+      disk_cache.put(PageKey::new(url.clone(), page_idx), &page_bytes[..])?;
+    }
+    Ok(())
+  }
+
+  fn invalidate_disk(&self, url: &Url) -> GResult<usize> {
+    match &self.disk_cache {
+      Some(disk_cache) => disk_cache.lock().unwrap().invalidate(url),
+      None => Ok(0),
+    }
+  }
+
+  fn count_request(&self, url: &Url, range: &Range, missing_ranges: &[Range]) {
+    let missing_length: usize = missing_ranges.iter()
+      .map(|missing_range| {
+        let offset_l = std::cmp::max(range.offset, missing_range.offset);
+        let offset_r = std::cmp::min(range.offset + range.length, missing_range.offset + missing_range.length);
+        offset_r.saturating_sub(offset_l)
+      })
+      .sum();
+    self.stats.count(url, |counters| {
+      match missing_ranges.is_empty() {
+        false => add(&counters.misses, 1),
+        true => add(&counters.hits, 1),
+      }
+      add(&counters.hit_bytes, range.length - missing_length);
+    });
+  }
+
+  // request bypassing the cache
+  fn count_direct(&self, url: &Url) {
+    self.stats.count(url, |counters| add(&counters.misses, 1));
+  }
+
+  fn count_fetch(&self, url: &Url, num_bytes: usize) {
+    self.stats.count(url, |counters| {
+      add(&counters.fetched_bytes, num_bytes);
+      add(&counters.fetches, 1);
+    });
+  }
+
+  fn record_hit(&self, url: &Url, range: &Range, start_time: Instant) -> GResult<()> {
+    match &self.recorder {
+      Some(recorder) => recorder.lock().unwrap().record(url, range, start_time, true),
+      None => Ok(()),
+    }
+  }
+
+  // runs of missing pages, in order, merged where one larger read is cheaper
+  fn missing_cache_ranges(&self, page_key: &mut PageKey, range: &Range) -> Vec<Range> {
+    let pages = self.range_to_pages(range);
+    let mut cached_pages = Intervals::empty(pages.len());
+    for page_idx in pages.clone() {
+      page_key.set_page(page_idx);
+      if !self.miss_cache(page_key) {
+        let page_pos = page_idx - pages.start;
+        cached_pages.fill(&(page_pos, page_pos + 1));
+      }
+    }
+    let mut missing_ranges: Vec<Range> = Vec::new();
+    for (run_l, run_r) in cached_pages.missing_runs(&(0, pages.len())) {
+      let missing_range = Range {
+        offset: (pages.start + run_l) * self.page_size,
+        length: (run_r - run_l) * self.page_size,
       };
-      let first_range = self.page_to_range(missing_left);
-      let last_range = self.page_to_range(missing_right);
-      let offset_l = first_range.offset;
-      let offset_r = last_range.offset + last_range.length;
-      return Some(Range { offset: offset_l, length: offset_r - offset_l })
+      match missing_ranges.last_mut() {
+        Some(last_range) if self.is_merge_cheaper(last_range, &missing_range) => Self::merge_into(last_range, &missing_range),
+        _ => missing_ranges.push(missing_range),
+      }
     }
-    None
+    missing_ranges
+  }
+
+  // whether to read both ranges of a blob at once, right starting no earlier than left
+  fn is_merge_cheaper(&self, left: &Range, right: &Range) -> bool {
+    if left.offset + left.length >= right.offset {
+      // overlapping or adjacent
+      return true;
+    }
+    match &self.profile {
+      Some(profile) => {
+        let merged_length = right.offset + right.length - left.offset;
+        profile.cost(merged_length) <= profile.sequential_cost(&[left.length, right.length])
+      },
+      None => false,
+    }
+  }
+
+  fn merge_into(left: &mut Range, right: &Range) {
+    let end = std::cmp::max(left.offset + left.length, right.offset + right.length);
+    left.length = end - left.offset;
   }
 
   fn miss_cache(&self, page_key: &PageKey) -> bool {
-    !self.page_cache.borrow_mut().contains(page_key)
+    !self.page_cache.contains(page_key)
   }
 
   fn read_through_page(&self, page_key: &PageKey) -> GResult<SharedByteSlice> {
     // check in cache
-    if let Some(cache_line) = self.page_cache.borrow_mut().get(page_key) {
+    if let Some(cache_line) = self.page_cache.get(page_key) {
       // cache hit
-      Ok(cache_line.clone())
+      Ok(cache_line)
     } else {
       // cache miss even after prepare (can happen if eviction occurs in between)
       log::warn!("Cache missing after prepare {:?}", page_key);
+      self.stats.count(&page_key.url, |counters| add(&counters.prepare_fallbacks, 1));
       self.read_range_raw(
         page_key,
         &Range { offset: page_key.page_idx * self.page_size, length: self.page_size },
@@ -245,7 +475,40 @@ impl ExternalStorage {
   }
 
   fn read_range_raw(&self, page_key: &PageKey, range: &Range) -> GResult<SharedByteSlice> {
-    Ok(self.select_adaptor(&page_key.url)?.read_range(&page_key.url, range)?.slice_all())
+    let bytes = self.select_adaptor(&page_key.url)?.read_range(&page_key.url, range)?;
+    self.count_fetch(&page_key.url, bytes.len());
+    Ok(bytes.slice_all())
+  }
+
+  fn read_batch_raw(&self, requests: &[ReadRequest]) -> GResult<Vec<SharedBytes>> {
+    // one batch per adaptor, then restore request order
+    let mut scheme_idxs: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (idx, request) in requests.iter().enumerate() {
+      scheme_idxs.entry(request.url().scheme()).or_default().push(idx);
+    }
+    let mut responses: Vec<Option<SharedBytes>> = vec![None; requests.len()];
+    for (_scheme, idxs) in scheme_idxs {
+      let adaptor = self.select_adaptor(requests[idxs[0]].url())?;
+      let scheme_requests: Vec<ReadRequest> = idxs.iter().map(|idx| requests[*idx].clone()).collect();
+      for (idx, response) in idxs.into_iter().zip(adaptor.read_batch(&scheme_requests)?) {
+        self.count_fetch(requests[idx].url(), response.len());
+        responses[idx] = Some(response);
+      }
+    }
+    Ok(responses.into_iter().map(|response| response.unwrap()).collect())
+  }
+
+  fn collect_view(&self, page_key: &mut PageKey, range: &Range) -> GResult<SharedByteView> {
+    let mut view = SharedByteView::default();
+    for page_idx in self.range_to_pages(range) {
+      page_key.set_page(page_idx);
+      let page_cache = self.read_through_page(page_key)?;
+      let page_range = self.page_to_range(page_idx);
+      let page_l = range.offset.saturating_sub(page_range.offset);
+      let page_r = std::cmp::min(page_cache.len(), (range.offset + range.length).saturating_sub(page_range.offset));
+      view.push(page_cache.slice(page_l, page_r - page_l))
+    }
+    Ok(view)
   }
 
   fn range_to_pages(&self, range: &Range) -> std::ops::Range<usize> {
@@ -260,50 +523,159 @@ impl ExternalStorage {
 
 impl ExternalStorage {
   pub fn read_all(&self, url: &Url) -> GResult<SharedBytes> {
-    self.select_adaptor(url)?.read_all(url)
+    let bytes = self.select_adaptor(url)?.read_all(url)?;
+    self.count_direct(url);
+    self.count_fetch(url, bytes.len());
+    Ok(bytes)
   }
 
   pub fn read_range(&self, url: &Url, range: &Range) -> GResult<SharedByteView> {
     let mut page_key = PageKey::new(url.clone(), 0);
     if range.length <= self.total_page * self.page_size {
       // warm up cache
-      self.prepare_cache(&mut page_key, range)?;
+      let start_time = Instant::now();
+      let missing_ranges = self.prepare_cache(&mut page_key, range)?;
       // tracing::trace!("internal_preparecache");
 
       // collect page bytes
-      let mut view = SharedByteView::default();
-      for page_idx in self.range_to_pages(range) {
-        page_key.set_page(page_idx);
-        let page_cache = self.read_through_page(&page_key)?;
-        let page_range = self.page_to_range(page_idx);
-        let page_l = range.offset.saturating_sub(page_range.offset);
-        let page_r = std::cmp::min(page_cache.len(), (range.offset + range.length).saturating_sub(page_range.offset));
-        view.push(page_cache.slice(page_l, page_r - page_l))
-      }
+      let view = self.collect_view(&mut page_key, range)?;
       // tracing::trace!("internal_compileview");
+      self.count_request(url, range, &missing_ranges);
+      if missing_ranges.is_empty() {
+        self.record_hit(url, range, start_time)?;
+      }
       Ok(view)
     } else {
       // range too large for the cache
+      self.count_direct(url);
       self.read_range_raw(&page_key, range).map(SharedByteView::from)
     }
   }
 
+  pub fn read_ranges(&self, url: &Url, ranges: &[Range]) -> GResult<Vec<SharedByteView>> {
+    let requests: Vec<ReadRequest> = ranges.iter()
+      .map(|range| ReadRequest::Range { url: url.clone(), range: range.clone() })
+      .collect();
+    self.read_batch(&requests)
+  }
+
+  pub fn read_batch(&self, requests: &[ReadRequest]) -> GResult<Vec<SharedByteView>> {
+    // plan: uncacheable requests go directly, cacheable ones only fetch their missing pages
+    let start_time = Instant::now();
+    let mut is_hits = vec![false; requests.len()];
+    let cache_capacity = self.cache_capacity();
+    let mut fetches: Vec<ReadRequest> = Vec::new();
+    let mut direct_fetch_idxs: Vec<Option<usize>> = vec![None; requests.len()];
+    let mut missing_ranges: Vec<(Url, Range)> = Vec::new();
+    let mut request_missing_ranges: Vec<Vec<Range>> = vec![Vec::new(); requests.len()];
+    for (idx, request) in requests.iter().enumerate() {
+      match request {
+        ReadRequest::Range { url, range } if range.length <= cache_capacity => {
+          let mut page_key = PageKey::new(url.clone(), 0);
+          self.load_disk_pages(&mut page_key, range)?;
+          let request_missing = self.missing_cache_ranges(&mut page_key, range);
+          is_hits[idx] = request_missing.is_empty();
+          missing_ranges.extend(request_missing.iter().map(|missing_range| (url.clone(), missing_range.clone())));
+          request_missing_ranges[idx] = request_missing;
+        },
+        _ => {
+          direct_fetch_idxs[idx] = Some(fetches.len());
+          fetches.push(request.clone());
+        },
+      }
+    }
+
+    // merge missing ranges of the same blob, overlapping or where one larger read is cheaper
+    missing_ranges.sort_by(|(url_a, range_a), (url_b, range_b)| {
+      (url_a.as_str(), range_a.offset).cmp(&(url_b.as_str(), range_b.offset))
+    });
+    let num_direct_fetches = fetches.len();
+    for (url, range) in missing_ranges {
+      let has_missing_fetch = fetches.len() > num_direct_fetches;
+      if let Some(ReadRequest::Range { url: last_url, range: last_range }) = fetches.last_mut() {
+        if has_missing_fetch && *last_url == url && self.is_merge_cheaper(last_range, &range) {
+          Self::merge_into(last_range, &range);
+          continue;
+        }
+      }
+      fetches.push(ReadRequest::Range { url, range });
+    }
+
+    // fetch everything at once and warm up cache
+    let mut responses = self.read_batch_raw(&fetches)?;
+    for (fetch, response) in fetches[num_direct_fetches..].iter().zip(responses[num_direct_fetches..].iter()) {
+      if let ReadRequest::Range { url, range } = fetch {
+        self.warm_cache_at(url, &response.slice_all(), range.offset);
+        self.persist_pages(url, &response.slice_all(), range.offset)?;
+      }
+    }
+    responses.truncate(num_direct_fetches);
+
+    // collect views in request order
+    let mut responses: Vec<Option<SharedBytes>> = responses.into_iter().map(Some).collect();
+    let views = requests.iter().zip(direct_fetch_idxs.iter().copied())
+      .map(|(request, direct_fetch_idx)| match (request, direct_fetch_idx) {
+        (_, Some(fetch_idx)) => Ok(SharedByteView::from(responses[fetch_idx].take().unwrap().slice_all())),
+        (ReadRequest::Range { url, range }, None) => self.collect_view(&mut PageKey::new(url.clone(), 0), range),
+        (ReadRequest::All { .. }, None) => unreachable!(),
+      })
+      .collect::<GResult<Vec<SharedByteView>>>()?;
+    for (request, direct_fetch_idx, missing_range) in izip!(requests, &direct_fetch_idxs, &request_missing_ranges) {
+      match (request, direct_fetch_idx) {
+        (ReadRequest::Range { url, range }, None) => self.count_request(url, range, missing_range),
+        _ => self.count_direct(request.url()),
+      }
+    }
+    for (request, is_hit) in requests.iter().zip(is_hits) {
+      if let (ReadRequest::Range { url, range }, true) = (request, is_hit) {
+        self.record_hit(url, range, start_time)?;
+      }
+    }
+    Ok(views)
+  }
+
+  // drop cached pages of the url, returns number of in-memory pages dropped
+  pub fn invalidate(&self, url: &Url) -> usize {
+    if let Err(e) = self.invalidate_disk(url) {
+      log::warn!("Failed to invalidate disk cache of {}, {}", url, e);
+    }
+    self.page_cache.invalidate(url).len()
+  }
+
+  // drop cached pages of all urls under the prefix, e.g. a directory url ending with /
+  pub fn invalidate_prefix(&self, prefix: &Url) -> usize {
+    if let Some(disk_cache) = &self.disk_cache {
+      if let Err(e) = disk_cache.lock().unwrap().invalidate_prefix(prefix) {
+        log::warn!("Failed to invalidate disk cache under {}, {}", prefix, e);
+      }
+    }
+    self.page_cache.invalidate_prefix(prefix).len()
+  }
+
+  // invalidate once the adaptor is done, so that concurrent readers cannot re-cache old pages
   pub fn create(&self, url: &Url) -> GResult<()> {
-    // TODO: use invalidate_entries_if and support_invalidation_closures to invalid some url
-    self.page_cache.borrow_mut().clear();
-    self.select_adaptor(url)?.create(url)
+    let result = self.select_adaptor(url)?.create(url);
+    self.invalidate_written(url)?;
+    result
   }
 
   pub fn write_all(&self, url: &Url, buf: &[u8]) -> GResult<()> {
-    // TODO: use invalidate_entries_if and support_invalidation_closures to invalid some url
-    self.page_cache.borrow_mut().clear();
-    self.select_adaptor(url)?.write_all(url, buf)
+    let result = self.select_adaptor(url)?.write_all(url, buf);
+    self.invalidate_written(url)?;
+    result
   }
 
   pub fn remove(&self, url: &Url) -> GResult<()> {
-    // TODO: use invalidate_entries_if and support_invalidation_closures to invalid some url
-    self.page_cache.borrow_mut().clear();
-    self.select_adaptor(url)?.remove(url)
+    let result = self.select_adaptor(url)?.remove(url);
+    self.invalidate_written(url)?;
+    result
+  }
+
+  // also after failed writes, which may have partially replaced the blob
+  fn invalidate_written(&self, url: &Url) -> GResult<()> {
+    self.page_cache.invalidate(url);
+    self.invalidate_disk(url)?;
+    Ok(())
   }
 }
 
@@ -311,13 +683,17 @@ impl ExternalStorage {
 #[cfg(test)]
 mod tests {
   use super::*;
-  use itertools::izip;
   use rand::Rng;
 
+  use crate::io::profile::Bandwidth;
+  use crate::io::profile::Latency;
   use crate::io::storage::adaptor_test::fsa_resources_setup;
   use crate::io::storage::adaptor_test::fsa_tempdir_setup;
-  use crate::io::storage::ReadRequest;
+  use crate::io::storage::FileSystemAdaptor;
   use crate::io::storage::url_from_dir_path;
+  use crate::io::trace::RecordingAdaptor;
+  use crate::io::trace::TraceRecorder;
+  use crate::io::trace::read_trace;
 
   /* generic unit tests */
 
@@ -421,49 +797,49 @@ mod tests {
   #[test]
   fn es_write_all_zero_ok() -> GResult<()> {
     let (temp_dir, fsa) = fsa_tempdir_setup()?;
-    let es = ExternalStorage::new_with_cache(65536, 100).with("file".to_string(), Box::new(fsa))?;
+    let es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
     write_all_zero_ok(es, &url_from_dir_path(temp_dir.path())?)
   }
 
   #[test]
   fn es_write_read_all_zero_ok() -> GResult<()> {
     let (temp_dir, fsa) = fsa_tempdir_setup()?;
-    let es = ExternalStorage::new_with_cache(65536, 100).with("file".to_string(), Box::new(fsa))?;
+    let es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
     write_read_all_zero_ok(es, &url_from_dir_path(temp_dir.path())?)
   }
 
   #[test]
   fn es_write_read_all_random_ok() -> GResult<()> {
     let (temp_dir, fsa) = fsa_tempdir_setup()?;
-    let es = ExternalStorage::new_with_cache(65536, 100).with("file".to_string(), Box::new(fsa))?;
+    let es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
     write_read_all_random_ok(es, &url_from_dir_path(temp_dir.path())?)
   }
 
   #[test]
   fn es_write_twice_read_all_random_ok() -> GResult<()> {
     let (temp_dir, fsa) = fsa_tempdir_setup()?;
-    let es = ExternalStorage::new_with_cache(65536, 100).with("file".to_string(), Box::new(fsa))?;
+    let es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
     write_twice_read_all_random_ok(es, &url_from_dir_path(temp_dir.path())?)
   }
 
   #[test]
   fn es_write_read_range_random_ok() -> GResult<()> {
     let (temp_dir, fsa) = fsa_tempdir_setup()?;
-    let es = ExternalStorage::new_with_cache(65536, 100).with("file".to_string(), Box::new(fsa))?;
+    let es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
     write_read_range_random_ok(es, &url_from_dir_path(temp_dir.path())?)
   }
 
   #[test]
   fn es_write_read_generic_random_ok() -> GResult<()> {
     let (temp_dir, fsa) = fsa_tempdir_setup()?;
-    let es = ExternalStorage::new_with_cache(65536, 100).with("file".to_string(), Box::new(fsa))?;
+    let es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
     write_read_generic_random_ok(es, &url_from_dir_path(temp_dir.path())?)
   }
 
   #[test]
   fn es_read_all_ok() -> GResult<()> {
     let (resource_dir, fsa) = fsa_resources_setup()?;
-    let es = ExternalStorage::new_with_cache(65536, 100).with("file".to_string(), Box::new(fsa))?;
+    let es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
     let buf = es.read_all(&resource_dir.join("small.txt")?)?;
     let read_string = match std::str::from_utf8(&buf[..]) {
       Ok(v) => v,
@@ -477,7 +853,7 @@ mod tests {
   fn es_read_batch_sequential() -> GResult<()> {
     let (temp_dir, fsa) = fsa_tempdir_setup()?;
     let temp_dir_url = &url_from_dir_path(temp_dir.path())?;
-    let es = ExternalStorage::new_with_cache(65536, 100).with("file".to_string(), Box::new(fsa))?;
+    let es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
 
     // write some data
     let test_path = temp_dir_url.join("test.bin")?;
@@ -517,4 +893,278 @@ mod tests {
 
     Ok(())
   }
-}
\ No newline at end of file
+  #[test]
+  fn es_read_batch_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let temp_dir_url = &url_from_dir_path(temp_dir.path())?;
+    let es = ExternalStorage::new_with_cache(2000, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
+
+    // write some data in two blobs
+    let test_paths = [temp_dir_url.join("test_1.bin")?, temp_dir_url.join("test_2.bin")?];
+    let mut test_datas = [[0u8; 4096]; 2];
+    for (test_path, test_data) in test_paths.iter().zip(test_datas.iter_mut()) {
+      rand::thread_rng().fill(&mut test_data[..]);
+      es.write_all(test_path, test_data)?;
+    }
+
+    // random ranges across blobs, some larger than the cache, plus a full read
+    let mut rng = rand::thread_rng();
+    let mut requests = vec![ReadRequest::All { url: test_paths[0].clone() }];
+    for _ in 0..100 {
+      let offset = rng.gen_range(0..4095);
+      let length = rng.gen_range(0..4096 - offset);
+      let blob_idx = rng.gen_range(0..2);
+      requests.push(ReadRequest::Range { url: test_paths[blob_idx].clone(), range: Range { offset, length } });
+    }
+    for _ in 0..2 {
+      // second round reads through warm cache
+      let responses = es.read_batch(&requests)?;
+      for (request, response) in izip!(&requests, &responses) {
+        let blob_idx = if request.url() == &test_paths[0] { 0 } else { 1 };
+        let test_data_expected = match request {
+          ReadRequest::All { .. } => &test_datas[blob_idx][..],
+          ReadRequest::Range { range, .. } => &test_datas[blob_idx][range.offset..range.offset+range.length],
+        };
+        assert_eq!(test_data_expected, response.clone_all(), "Reread data not matched with original one");
+      }
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn es_evicting_policies_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let test_path = url_from_dir_path(temp_dir.path())?.join("test.bin")?;
+    let mut test_data = [0u8; 4096];
+    rand::thread_rng().fill(&mut test_data[..]);
+    fsa.write_all(&test_path, &test_data)?;
+
+    // cache of 10 pages under random ranges, constantly evicting
+    let mut rng = rand::thread_rng();
+    for policy in [CachePolicy::Fifo, CachePolicy::Lru, CachePolicy::Clock, CachePolicy::TwoQ] {
+      let es = ExternalStorage::new_with_cache(1000, 100, policy).with("file".to_string(), Box::new(FileSystemAdaptor::new()))?;
+      for _ in 0..200 {
+        let offset = rng.gen_range(0..test_data.len() - 1);
+        let length = rng.gen_range(0..std::cmp::min(1000, test_data.len() - offset));
+        let test_data_range = es.read_range(&test_path, &Range { offset, length })?;
+        assert_eq!(&test_data[offset..offset+length], test_data_range.clone_all(), "Reread mismatched with {:?}", policy);
+      }
+      assert!(es.page_cache.len() <= 10);
+      let num_pages = es.page_cache.len();
+      assert_eq!(es.invalidate(&test_path), num_pages, "Url index out of sync with {:?}", policy);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn es_invalidate_by_url_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let base_url = url_from_dir_path(temp_dir.path())?;
+    let layer_url = base_url.join("layers/")?;
+    let data_path = base_url.join("data.bin")?;
+    let layer_paths = [layer_url.join("layer_0.bin")?, layer_url.join("layer_1.bin")?];
+    let es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
+    es.write_all(&data_path, &[1u8; 1000])?;
+    for layer_path in &layer_paths {
+      es.write_all(layer_path, &[2u8; 500])?;
+    }
+    es.read_range(&data_path, &Range { offset: 0, length: 1000 })?;
+    for layer_path in &layer_paths {
+      es.read_range(layer_path, &Range { offset: 0, length: 500 })?;
+    }
+    assert_eq!(es.page_cache.len(), 20);
+
+    // rewriting one layer keeps pages of other blobs, and rereads new data
+    es.write_all(&layer_paths[0], &[3u8; 300])?;
+    assert_eq!(es.page_cache.len(), 15);
+    assert_eq!(es.read_range(&layer_paths[0], &Range { offset: 0, length: 300 })?.clone_all(), vec![3u8; 300]);
+    assert_eq!(es.page_cache.len(), 18);
+
+    // drop all layers but keep data blob
+    assert_eq!(es.invalidate_prefix(&layer_url), 8);
+    assert_eq!(es.page_cache.len(), 10);
+    es.remove(&data_path)?;
+    assert_eq!(es.page_cache.len(), 0);
+    assert_eq!(es.invalidate(&data_path), 0);
+    Ok(())
+  }
+
+  #[test]
+  fn es_concurrent_stats_ok() -> GResult<()> {
+    let mut es = ExternalStorage::new_with_cache(1 << 20, 100, CachePolicy::default())
+      .with("mem".to_string(), Box::new(crate::io::storage::MemoryAdaptor::new()))?;
+    let data_url = Url::parse("mem:///data/blob")?;
+    es.track_prefix(&Url::parse("mem:///data/")?);
+    es.write_all(&data_url, &[3u8; 10000])?;
+
+    // every request counted exactly once across threads
+    let num_threads = 8;
+    let reads_per_thread = 500;
+    std::thread::scope(|scope| {
+      for thread_idx in 0..num_threads {
+        let (es, data_url) = (&es, &data_url);
+        scope.spawn(move || {
+          for read_idx in 0..reads_per_thread {
+            let offset = (thread_idx * 37 + read_idx * 101) % 9900;
+            es.read_range(data_url, &Range { offset, length: 100 }).expect("Failed to read");
+          }
+        });
+      }
+    });
+    let stats = es.stats();
+    assert_eq!(stats.total.hits + stats.total.misses, num_threads * reads_per_thread);
+    assert_eq!(stats.by_scheme["mem"], stats.total);
+    assert_eq!(stats.by_prefix["mem:///data/"], stats.total);
+    Ok(())
+  }
+
+  #[test]
+  fn es_cache_stats_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let base_url = url_from_dir_path(temp_dir.path())?;
+    let layer_url = base_url.join("layers/")?;
+    let data_path = base_url.join("data.bin")?;
+    let layer_path = layer_url.join("layer_0.bin")?;
+    let mut es = ExternalStorage::new_with_cache(500, 100, CachePolicy::default()).with("file".to_string(), Box::new(fsa))?;
+    es.track_prefix(&layer_url);
+    es.write_all(&data_path, &[1u8; 1000])?;
+    es.write_all(&layer_path, &[2u8; 1000])?;
+
+    // miss then hits, partially cached request only counts bytes in cached pages
+    es.read_range(&layer_path, &Range { offset: 150, length: 100 })?;
+    es.read_range(&layer_path, &Range { offset: 180, length: 20 })?;
+    es.read_range(&layer_path, &Range { offset: 250, length: 100 })?;
+    let layer_counters = CacheCounters { hits: 1, misses: 2, hit_bytes: 70, fetched_bytes: 300, fetches: 2, evictions: 0, prepare_fallbacks: 0, disk_pages: 0 };
+    assert_eq!(es.stats().by_prefix[layer_url.as_str()], layer_counters);
+
+    // data blob evicts layer pages, oversized and batched requests
+    es.read_range(&data_path, &Range { offset: 0, length: 400 })?;
+    es.read_range(&data_path, &Range { offset: 0, length: 600 })?;
+    es.read_batch(&[
+      ReadRequest::Range { url: data_path.clone(), range: Range { offset: 350, length: 100 } },
+      ReadRequest::All { url: layer_path.clone() },
+    ])?;
+    let stats = es.stats();
+    assert_eq!(stats.by_prefix[layer_url.as_str()].evictions, 3);
+    assert_eq!(stats.by_prefix[layer_url.as_str()].fetched_bytes, 1300);
+    assert_eq!(stats.by_scheme["file"], stats.total);
+    assert_eq!(stats.total.hits, 1);
+    assert_eq!(stats.total.misses, 6);
+    assert_eq!(stats.total.hit_bytes, 70 + 50);
+    assert_eq!(stats.total.fetched_bytes, 300 + 400 + 600 + 100 + 1000);
+
+    // reset keeps tracked prefixes
+    es.reset_stats();
+    assert_eq!(es.stats().by_prefix[layer_url.as_str()], CacheCounters::default());
+    assert_eq!(es.stats().total, CacheCounters::default());
+    Ok(())
+  }
+
+  #[test]
+  fn es_fetch_missing_gaps_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let test_path = url_from_dir_path(temp_dir.path())?.join("test.bin")?;
+    let mut test_data = [0u8; 1000];
+    rand::thread_rng().fill(&mut test_data[..]);
+    fsa.write_all(&test_path, &test_data)?;
+    let open_es = |profile: Option<Box<dyn StorageProfile>>| -> GResult<ExternalStorage> {
+      let mut es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default())
+        .with("file".to_string(), Box::new(FileSystemAdaptor::new()))?;
+      if let Some(profile) = profile {
+        es.set_profile(profile);
+      }
+      // pages 2, 3 and 6 cached
+      es.read_range(&test_path, &Range { offset: 200, length: 200 })?;
+      es.read_range(&test_path, &Range { offset: 600, length: 100 })?;
+      es.reset_stats();
+      Ok(es)
+    };
+
+    // only gaps around cached pages, also bandwidth-bound profile never merges
+    for profile in [None, Some(Box::new(Bandwidth::from_mbps(100.0)) as Box<dyn StorageProfile>)] {
+      let es = open_es(profile)?;
+      assert_eq!(es.read_range(&test_path, &Range { offset: 50, length: 900 })?.clone_all(), &test_data[50..950]);
+      assert_eq!(es.stats().total.fetched_bytes, 700);
+      assert_eq!(es.stats().total.fetches, 3);
+      assert_eq!(es.stats().total.hit_bytes, 300);
+    }
+
+    // latency-bound profile merges into one read, in read_batch as well
+    let es = open_es(Some(Box::new(Latency::from_millis(10))))?;
+    assert_eq!(es.read_range(&test_path, &Range { offset: 150, length: 600 })?.clone_all(), &test_data[150..750]);
+    assert_eq!(es.stats().total.fetched_bytes, 700);
+    assert_eq!(es.stats().total.fetches, 1);
+    let es = open_es(Some(Box::new(Latency::from_millis(10))))?;
+    let views = es.read_batch(&[
+      ReadRequest::Range { url: test_path.clone(), range: Range { offset: 100, length: 50 } },
+      ReadRequest::Range { url: test_path.clone(), range: Range { offset: 400, length: 150 } },
+    ])?;
+    assert_eq!(views[0].clone_all(), &test_data[100..150]);
+    assert_eq!(views[1].clone_all(), &test_data[400..550]);
+    assert_eq!(es.stats().total.fetched_bytes, 500);
+    Ok(())
+  }
+
+  #[test]
+  fn es_disk_cache_across_restart_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let test_path = url_from_dir_path(temp_dir.path())?.join("test.bin")?;
+    let disk_cache_dir = temp_dir.path().join("disk_cache");
+    let mut test_data = [0u8; 1000];
+    rand::thread_rng().fill(&mut test_data[..]);
+    fsa.write_all(&test_path, &test_data)?;
+    let open_es = || -> GResult<ExternalStorage> {
+      let mut es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default())
+        .with("file".to_string(), Box::new(FileSystemAdaptor::new()))?;
+      es.set_disk_cache(DiskCache::open(&disk_cache_dir, 65536, 100, CachePolicy::Lru)?, vec!["file".to_string()]);
+      Ok(es)
+    };
+
+    // first process fetches and persists pages
+    let es = open_es()?;
+    assert_eq!(es.read_range(&test_path, &Range { offset: 150, length: 300 })?.clone_all(), &test_data[150..450]);
+    assert_eq!(es.stats().total.fetched_bytes, 400);
+    drop(es);
+
+    // second process only fetches pages not on disk
+    let es = open_es()?;
+    assert_eq!(es.read_range(&test_path, &Range { offset: 150, length: 300 })?.clone_all(), &test_data[150..450]);
+    let views = es.read_ranges(&test_path, &[Range { offset: 0, length: 50 }, Range { offset: 420, length: 100 }])?;
+    assert_eq!(views[0].clone_all(), &test_data[0..50]);
+    assert_eq!(views[1].clone_all(), &test_data[420..520]);
+    let stats = es.stats();
+    assert_eq!((stats.total.hits, stats.total.fetched_bytes, stats.total.disk_pages), (1, 200, 4));
+    drop(es);
+
+    // blob rewritten by someone else, stale pages are dropped
+    let new_data = [7u8; 500];
+    fsa.write_all(&test_path, &new_data)?;
+    let es = open_es()?;
+    assert_eq!(es.read_range(&test_path, &Range { offset: 150, length: 300 })?.clone_all(), &new_data[150..450]);
+    assert_eq!(es.stats().total.disk_pages, 0);
+    Ok(())
+  }
+
+  #[test]
+  fn es_trace_cache_hit_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let test_path = url_from_dir_path(temp_dir.path())?.join("test.bin")?;
+    let trace_path = temp_dir.path().join("trace.jsonl");
+    let recorder = TraceRecorder::create(&trace_path)?;
+    let rfsa = RecordingAdaptor::new(Box::new(fsa), Arc::clone(&recorder));
+    let mut es = ExternalStorage::new_with_cache(65536, 100, CachePolicy::default()).with("file".to_string(), Box::new(rfsa))?;
+    es.set_trace_recorder(Arc::clone(&recorder));
+    es.write_all(&test_path, &[1u8; 1000])?;
+
+    // miss on the adaptor, then hit on the cache
+    es.read_range(&test_path, &Range { offset: 150, length: 100 })?;
+    es.read_range(&test_path, &Range { offset: 180, length: 20 })?;
+    es.read_batch(&[ReadRequest::Range { url: test_path.clone(), range: Range { offset: 100, length: 50 } }])?;
+    recorder.lock().unwrap().flush()?;
+
+    let events = read_trace(&trace_path)?;
+    let hit_and_lengths: Vec<(bool, usize)> = events.iter().map(|event| (event.cache_hit, event.length)).collect();
+    assert_eq!(hit_and_lengths, vec![(false, 200), (true, 20), (true, 50)]);
+    Ok(())
+  }
+}
@@ -29,6 +29,19 @@ impl Intervals {
       })
   }
 
+  // maximal missing sub-intervals, in order
+  pub fn missing_runs(&self, interval: &Interval) -> Vec<Interval> {
+    let mut runs: Vec<Interval> = Vec::new();
+    for missing_idx in self.flags[interval.0 .. interval.1].iter_zeros() {
+      let idx = missing_idx + interval.0;
+      match runs.last_mut() {
+        Some(run) if run.1 == idx => run.1 += 1,
+        _ => runs.push((idx, idx + 1)),
+      }
+    }
+    runs
+  }
+
   pub fn fill(&mut self, interval: &Interval) {
     self.flags.get_mut(interval.0 .. interval.1).unwrap().fill(true);
   }
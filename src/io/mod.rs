@@ -1,4 +1,10 @@
 pub mod storage;
 pub mod internal;
 pub mod profile;
-pub mod intervals;
\ No newline at end of file
+pub mod price;
+pub mod intervals;
+pub mod trace;
+pub mod retry;
+pub mod cache;
+pub mod disk_cache;
+pub mod calibrate;
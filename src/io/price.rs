@@ -0,0 +1,103 @@
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt::Debug;
+
+use crate::io::internal::CacheCounters;
+
+pub trait PriceProfile: Send + Sync + Debug {
+  // estimate dollars charged for a read of size (read_size in bytes)
+  fn price(&self, read_size: usize) -> f64;
+
+  fn clone_box(&self) -> Box<dyn PriceProfile>;
+  fn to_meta(&self) -> PriceProfileMeta;
+
+  fn sequential_price(&self, read_sizes: &[usize]) -> f64 {
+    read_sizes.iter().map(|read_size| self.price(*read_size)).sum()
+  }
+}
+
+
+/* Affine (per request and per byte, e.g. GET operations and egress) */
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct AffinePriceProfile {
+  per_request: f64,  // dollars
+  per_byte: f64,  // dollars
+}
+
+impl AffinePriceProfile {
+  pub fn new(per_request: f64, per_byte: f64) -> AffinePriceProfile {
+    AffinePriceProfile { per_request, per_byte }
+  }
+
+  // as listed by providers, per 1000 requests and per GB
+  pub fn from_listed(per_kilo_requests: f64, per_gb: f64) -> AffinePriceProfile {
+    AffinePriceProfile::new(per_kilo_requests / 1e3, per_gb / (1u64 << 30) as f64)
+  }
+}
+
+impl PriceProfile for AffinePriceProfile {
+  fn price(&self, read_size: usize) -> f64 {
+    self.per_request + self.per_byte * read_size as f64
+  }
+
+  fn clone_box(&self) -> Box<dyn PriceProfile> {
+    Box::new(self.clone())
+  }
+  fn to_meta(&self) -> PriceProfileMeta {
+    PriceProfileMeta::Affine { meta: self.clone() }
+  }
+}
+
+
+/* Serialization */
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum PriceProfileMeta {
+  Affine { meta: AffinePriceProfile },
+}
+
+impl PriceProfileMeta {
+  pub fn from_meta(meta: PriceProfileMeta) -> Box<dyn PriceProfile> {
+    match meta {
+      PriceProfileMeta::Affine { meta } => Box::new(meta),
+    }
+  }
+}
+
+
+/* Reporting */
+
+// dollars per million lookups from reads observed over num_lookups, assuming prices affine in read size
+pub fn price_per_million_lookups(profile: &dyn PriceProfile, counters: &CacheCounters, num_lookups: usize) -> f64 {
+  if counters.fetches == 0 || num_lookups == 0 {
+    return 0.0;
+  }
+  let average_fetch_size = counters.fetched_bytes / counters.fetches;
+  let total_price = profile.price(average_fetch_size) * counters.fetches as f64;
+  total_price / num_lookups as f64 * 1e6
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn affine_price_test() {
+    let profile = AffinePriceProfile::from_listed(0.4, 0.09);
+    assert!((profile.price(0) - 0.0004).abs() < 1e-12);
+    assert!((profile.price(1 << 30) - (0.0004 + 0.09)).abs() < 1e-12);
+    assert!((profile.sequential_price(&[0, 0, 0]) - 0.0012).abs() < 1e-12);
+  }
+
+  #[test]
+  fn price_per_million_lookups_test() {
+    let profile = AffinePriceProfile::new(1e-6, 1e-9);
+    let counters = CacheCounters { fetches: 3, fetched_bytes: 3000, ..CacheCounters::default() };
+    // 3 requests and 3000 bytes over 2 lookups
+    let expected = (3.0 * 1e-6 + 3000.0 * 1e-9) / 2.0 * 1e6;
+    assert!((price_per_million_lookups(&profile, &counters, 2) - expected).abs() < 1e-9);
+    assert_eq!(price_per_million_lookups(&profile, &CacheCounters::default(), 2), 0.0);
+  }
+}
@@ -1,8 +1,15 @@
+use serde::Deserialize;
+use serde::Serialize;
 use std::any::Any;
 use std::fmt::Debug;
+use std::path::Path;
 use std::time::Duration;
 
-pub trait StorageProfile: Sync + Debug {
+use crate::common::error::GResult;
+use crate::io::price::PriceProfile;
+use crate::io::price::PriceProfileMeta;
+
+pub trait StorageProfile: StorageProfileMetaserde + Send + Sync + Debug {
   // estimate cost for a read of size (read_size in bytes), output in nanoseconds
   fn cost(&self, read_size: usize) -> Duration;
 
@@ -32,10 +39,16 @@ impl StorageProfile for Latency {
   }
 }
 
+impl StorageProfileMetaserde for Latency {
+  fn to_meta(&self) -> StorageProfileMeta {
+    StorageProfileMeta::Latency { meta: *self }
+  }
+}
+
 
 /* Bandwidth (linear) */
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct Bandwidth {
   pub nspb: f64,  // in ns per byte
 }
@@ -59,10 +72,16 @@ impl StorageProfile for Bandwidth {
   }
 }
 
+impl StorageProfileMetaserde for Bandwidth {
+  fn to_meta(&self) -> StorageProfileMeta {
+    StorageProfileMeta::Bandwidth { meta: self.clone() }
+  }
+}
+
 
 /* Latency (constant) */
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct AffineStorageProfile {
   latency: Duration,
   bandwidth: Bandwidth,
@@ -87,10 +106,287 @@ impl StorageProfile for AffineStorageProfile {
   }
 }
 
+impl StorageProfileMetaserde for AffineStorageProfile {
+  fn to_meta(&self) -> StorageProfileMeta {
+    StorageProfileMeta::Affine { meta: self.clone() }
+  }
+}
+
+
+/* Tabulated (measured points, linear in between) */
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct TabulatedStorageProfile {
+  points: Vec<(usize, Duration)>,  // (read_size, cost), sorted by distinct read sizes
+}
+
+impl TabulatedStorageProfile {
+  pub fn new(mut points: Vec<(usize, Duration)>) -> TabulatedStorageProfile {
+    assert!(!points.is_empty(), "Tabulated profile requires at least one point");
+    points.sort_by_key(|(read_size, _)| *read_size);
+    points.dedup_by_key(|(read_size, _)| *read_size);
+    TabulatedStorageProfile { points }
+  }
+}
+
+impl StorageProfile for TabulatedStorageProfile {
+  // smaller reads cost as the smallest point, larger ones extend the last segment
+  fn cost(&self, read_size: usize) -> Duration {
+    let idx = self.points.partition_point(|(point_size, _)| *point_size < read_size);
+    if idx == 0 || self.points.len() == 1 {
+      return self.points[0].1;
+    }
+    if idx < self.points.len() && self.points[idx].0 == read_size {
+      return self.points[idx].1;
+    }
+    let idx_r = std::cmp::min(idx, self.points.len() - 1);
+    let (size_l, cost_l) = self.points[idx_r - 1];
+    let (size_r, cost_r) = self.points[idx_r];
+    let slope = (cost_r.as_nanos() as f64 - cost_l.as_nanos() as f64) / (size_r - size_l) as f64;
+    let cost_ns = cost_l.as_nanos() as f64 + slope * (read_size - size_l) as f64;
+    Duration::from_nanos(cost_ns.max(0.0) as u64)
+  }
+
+  fn clone_box(&self) -> Box<dyn StorageProfile> {
+    Box::new(self.clone())
+  }
+  fn eq_box(&self, other: &dyn Any) -> bool {
+    other.downcast_ref::<Self>().is_some_and(|other| self == other)
+  }
+}
+
+impl StorageProfileMetaserde for TabulatedStorageProfile {
+  fn to_meta(&self) -> StorageProfileMeta {
+    StorageProfileMeta::Tabulated { meta: self.clone() }
+  }
+}
+
+
+/* Piecewise affine (e.g. steps at device page sizes, throughput knees) */
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct PiecewiseAffineStorageProfile {
+  pieces: Vec<(usize, AffineStorageProfile)>,  // (smallest read size, profile), sorted by distinct sizes
+}
+
+impl PiecewiseAffineStorageProfile {
+  pub fn new(mut pieces: Vec<(usize, AffineStorageProfile)>) -> PiecewiseAffineStorageProfile {
+    assert!(!pieces.is_empty(), "Piecewise profile requires at least one piece");
+    pieces.sort_by_key(|(min_size, _)| *min_size);
+    pieces.dedup_by_key(|(min_size, _)| *min_size);
+    PiecewiseAffineStorageProfile { pieces }
+  }
+}
+
+impl StorageProfile for PiecewiseAffineStorageProfile {
+  // reads smaller than the first piece use the first piece
+  fn cost(&self, read_size: usize) -> Duration {
+    let idx = self.pieces.partition_point(|(min_size, _)| *min_size <= read_size);
+    self.pieces[idx.saturating_sub(1)].1.cost(read_size)
+  }
+
+  fn clone_box(&self) -> Box<dyn StorageProfile> {
+    Box::new(self.clone())
+  }
+  fn eq_box(&self, other: &dyn Any) -> bool {
+    other.downcast_ref::<Self>().is_some_and(|other| self == other)
+  }
+}
+
+impl StorageProfileMetaserde for PiecewiseAffineStorageProfile {
+  fn to_meta(&self) -> StorageProfileMeta {
+    StorageProfileMeta::PiecewiseAffine { meta: self.clone() }
+  }
+}
+
+
+/* Tiered (expected cost over cache hits and misses) */
+
+#[derive(Debug)]
+pub struct TieredStorageProfile {
+  hit: Box<dyn StorageProfile>,  // e.g. memory copy
+  miss: Box<dyn StorageProfile>,  // e.g. remote storage
+  hit_probability: f64,
+}
+
+impl TieredStorageProfile {
+  pub fn new(hit: Box<dyn StorageProfile>, miss: Box<dyn StorageProfile>, hit_probability: f64) -> TieredStorageProfile {
+    assert!((0.0..=1.0).contains(&hit_probability), "Hit probability must be in [0, 1], got {}", hit_probability);
+    TieredStorageProfile { hit, miss, hit_probability }
+  }
+
+  // same tiers for a layer or read with another hit probability
+  pub fn with_hit_probability(&self, hit_probability: f64) -> TieredStorageProfile {
+    TieredStorageProfile::new(self.hit.clone_box(), self.miss.clone_box(), hit_probability)
+  }
+
+  pub fn hit_probability(&self) -> f64 {
+    self.hit_probability
+  }
+}
+
+impl Clone for TieredStorageProfile {
+  fn clone(&self) -> Self {
+    self.with_hit_probability(self.hit_probability)
+  }
+}
+
+impl PartialEq for TieredStorageProfile {
+  fn eq(&self, other: &Self) -> bool {
+    self.to_meta() == other.to_meta()
+  }
+}
+
+impl StorageProfile for TieredStorageProfile {
+  fn cost(&self, read_size: usize) -> Duration {
+    self.hit.cost(read_size).mul_f64(self.hit_probability)
+      + self.miss.cost(read_size).mul_f64(1.0 - self.hit_probability)
+  }
+
+  fn clone_box(&self) -> Box<dyn StorageProfile> {
+    Box::new(self.clone())
+  }
+  fn eq_box(&self, other: &dyn Any) -> bool {
+    other.downcast_ref::<Self>().is_some_and(|other| self == other)
+  }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct TieredStorageProfileMeta {
+  hit: Box<StorageProfileMeta>,
+  miss: Box<StorageProfileMeta>,
+  hit_probability: f64,
+}
+
+impl StorageProfileMetaserde for TieredStorageProfile {
+  fn to_meta(&self) -> StorageProfileMeta {
+    StorageProfileMeta::Tiered {
+      meta: TieredStorageProfileMeta {
+        hit: Box::new(self.hit.to_meta()),
+        miss: Box::new(self.miss.to_meta()),
+        hit_probability: self.hit_probability,
+      }
+    }
+  }
+}
+
+
+/* Weighted (latency plus dollars, for pay-per-request storage) */
+
+#[derive(Debug)]
+pub struct WeightedStorageProfile {
+  latency: Box<dyn StorageProfile>,
+  price: Box<dyn PriceProfile>,
+  seconds_per_dollar: f64,  // weight of price against latency
+}
+
+impl WeightedStorageProfile {
+  pub fn new(latency: Box<dyn StorageProfile>, price: Box<dyn PriceProfile>, seconds_per_dollar: f64) -> WeightedStorageProfile {
+    assert!(seconds_per_dollar >= 0.0, "Price weight must be non-negative, got {}", seconds_per_dollar);
+    WeightedStorageProfile { latency, price, seconds_per_dollar }
+  }
+}
+
+impl Clone for WeightedStorageProfile {
+  fn clone(&self) -> Self {
+    WeightedStorageProfile::new(self.latency.clone_box(), self.price.clone_box(), self.seconds_per_dollar)
+  }
+}
+
+impl PartialEq for WeightedStorageProfile {
+  fn eq(&self, other: &Self) -> bool {
+    self.to_meta() == other.to_meta()
+  }
+}
+
+impl StorageProfile for WeightedStorageProfile {
+  fn cost(&self, read_size: usize) -> Duration {
+    self.latency.cost(read_size) + Duration::from_secs_f64(self.price.price(read_size) * self.seconds_per_dollar)
+  }
+
+  fn clone_box(&self) -> Box<dyn StorageProfile> {
+    Box::new(self.clone())
+  }
+  fn eq_box(&self, other: &dyn Any) -> bool {
+    other.downcast_ref::<Self>().is_some_and(|other| self == other)
+  }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct WeightedStorageProfileMeta {
+  latency: Box<StorageProfileMeta>,
+  price: PriceProfileMeta,
+  seconds_per_dollar: f64,
+}
+
+impl StorageProfileMetaserde for WeightedStorageProfile {
+  fn to_meta(&self) -> StorageProfileMeta {
+    StorageProfileMeta::Weighted {
+      meta: WeightedStorageProfileMeta {
+        latency: Box::new(self.latency.to_meta()),
+        price: self.price.to_meta(),
+        seconds_per_dollar: self.seconds_per_dollar,
+      }
+    }
+  }
+}
+
+
+/* Serialization */
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum StorageProfileMeta {
+  Latency { meta: Latency },
+  Bandwidth { meta: Bandwidth },
+  Affine { meta: AffineStorageProfile },
+  Tabulated { meta: TabulatedStorageProfile },
+  PiecewiseAffine { meta: PiecewiseAffineStorageProfile },
+  Tiered { meta: TieredStorageProfileMeta },
+  Weighted { meta: WeightedStorageProfileMeta },
+}
+
+pub trait StorageProfileMetaserde {
+  fn to_meta(&self) -> StorageProfileMeta;
+}
+
+impl StorageProfileMeta {
+  pub fn from_meta(meta: StorageProfileMeta) -> Box<dyn StorageProfile> {
+    match meta {
+      StorageProfileMeta::Latency { meta } => Box::new(meta),
+      StorageProfileMeta::Bandwidth { meta } => Box::new(meta),
+      StorageProfileMeta::Affine { meta } => Box::new(meta),
+      // normalize points/pieces edited by hand
+      StorageProfileMeta::Tabulated { meta } => Box::new(TabulatedStorageProfile::new(meta.points)),
+      StorageProfileMeta::PiecewiseAffine { meta } => Box::new(PiecewiseAffineStorageProfile::new(meta.pieces)),
+      StorageProfileMeta::Tiered { meta } => Box::new(TieredStorageProfile::new(
+        StorageProfileMeta::from_meta(*meta.hit),
+        StorageProfileMeta::from_meta(*meta.miss),
+        meta.hit_probability,
+      )),
+      StorageProfileMeta::Weighted { meta } => Box::new(WeightedStorageProfile::new(
+        StorageProfileMeta::from_meta(*meta.latency),
+        PriceProfileMeta::from_meta(meta.price),
+        meta.seconds_per_dollar,
+      )),
+    }
+  }
+}
+
+pub fn save_profile(path: &Path, profile: &dyn StorageProfile) -> GResult<()> {
+  std::fs::write(path, serde_json::to_string_pretty(&profile.to_meta())?)?;
+  Ok(())
+}
+
+pub fn load_profile(path: &Path) -> GResult<Box<dyn StorageProfile>> {
+  let meta: StorageProfileMeta = serde_json::from_slice(&std::fs::read(path)?)?;
+  Ok(StorageProfileMeta::from_meta(meta))
+}
+
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::io::price::AffinePriceProfile;
   
   #[test]
   fn latency_test() {
@@ -122,6 +418,86 @@ mod tests {
     assert_eq!(profile.cost(1), Duration::from_micros(1000000 + 1));
   }
   
+  #[test]
+  fn tabulated_test() {
+    let profile = TabulatedStorageProfile::new(vec![
+      (4096, Duration::from_micros(100)),
+      (1024, Duration::from_micros(100)),
+      (65536, Duration::from_micros(700)),
+    ]);
+    assert_eq!(profile.cost(1), Duration::from_micros(100));
+    assert_eq!(profile.cost(2048), Duration::from_micros(100));
+    assert_eq!(profile.cost(4096), Duration::from_micros(100));
+    assert_eq!(profile.cost(4096 + 30720), Duration::from_micros(400));
+    assert_eq!(profile.cost(65536 + 61440), Duration::from_micros(1300));
+    assert_eq!(TabulatedStorageProfile::new(vec![(10, Duration::from_secs(1))]).cost(1000), Duration::from_secs(1));
+  }
+
+  #[test]
+  fn piecewise_affine_test() {
+    let profile = PiecewiseAffineStorageProfile::new(vec![
+      (1 << 20, AffineStorageProfile::new(Latency::from_millis(10), Bandwidth::from_mbps(10.0))),
+      (0, AffineStorageProfile::new(Latency::from_millis(1), Bandwidth::from_mbps(1.0))),
+    ]);
+    assert_eq!(profile.cost(1000), Duration::from_micros(1000 + 1000));
+    assert_eq!(profile.cost((1 << 20) - 1), Duration::from_micros(1000 + 1048575));
+    assert_eq!(profile.cost(1 << 20), Duration::from_nanos(10_000_000 + 104_857_600));
+  }
+
+  #[test]
+  fn tiered_test() {
+    let profile = TieredStorageProfile::new(
+      Box::new(Latency::from_micros(10)),
+      Box::new(AffineStorageProfile::new(Latency::from_millis(10), Bandwidth::from_mbps(1.0))),
+      0.75,
+    );
+    assert_eq!(profile.cost(1000), Duration::from_nanos(7_500 + 2_750_000));
+    assert_eq!(profile.with_hit_probability(1.0).cost(1000), Duration::from_micros(10));
+    assert_eq!(profile.with_hit_probability(0.0).cost(1000), Duration::from_micros(10_000 + 1_000));
+    assert_eq!(profile.clone(), profile);
+    assert_ne!(profile.with_hit_probability(0.5), profile);
+  }
+
+  #[test]
+  fn weighted_test() {
+    let latency = AffineStorageProfile::new(Latency::from_millis(10), Bandwidth::from_mbps(100.0));
+    let price = AffinePriceProfile::new(4e-7, 1e-10);
+    let profile = WeightedStorageProfile::new(Box::new(latency.clone()), Box::new(price.clone()), 1000.0);
+    // 400 us per request and 100 ns per byte on top of latency
+    assert_eq!(profile.cost(0), Duration::from_micros(10_000 + 400));
+    assert_eq!(profile.cost(1_000_000), Duration::from_micros(10_000 + 10_000 + 400 + 100_000));
+    let latency_only = WeightedStorageProfile::new(Box::new(latency), Box::new(price), 0.0);
+    assert_eq!(latency_only.cost(1_000_000), Duration::from_micros(20_000));
+  }
+
+  #[test]
+  fn metaserde_roundtrip_test() -> GResult<()> {
+    let profiles: Vec<Box<dyn StorageProfile>> = vec![
+      Box::new(Latency::from_millis(3)),
+      Box::new(Bandwidth::from_mbps(50.0)),
+      Box::new(AffineStorageProfile::new(Latency::from_millis(1), Bandwidth::from_mbps(1.0))),
+      Box::new(TabulatedStorageProfile::new(vec![(1, Duration::from_micros(5)), (100, Duration::from_micros(9))])),
+      Box::new(PiecewiseAffineStorageProfile::new(vec![(0, AffineStorageProfile::new(Latency::from_millis(1), Bandwidth::from_mbps(1.0)))])),
+      Box::new(TieredStorageProfile::new(Box::new(Latency::from_micros(1)), Box::new(Bandwidth::from_mbps(1.0)), 0.25)),
+      Box::new(WeightedStorageProfile::new(Box::new(Latency::from_millis(1)), Box::new(AffinePriceProfile::new(1e-6, 1e-9)), 100.0)),
+    ];
+    let temp_dir = tempfile::TempDir::new()?;
+    let profile_path = temp_dir.path().join("profile.json");
+    for profile in profiles {
+      save_profile(&profile_path, profile.as_ref())?;
+      let reloaded = load_profile(&profile_path)?;
+      assert_eq!(reloaded.to_meta(), profile.to_meta());
+      assert_eq!(reloaded.cost(4096), profile.cost(4096));
+    }
+
+    // hand-written tables are sorted on load
+    let meta: StorageProfileMeta = serde_json::from_str(r#"{"Tabulated":{"meta":{"points":[[100,{"secs":0,"nanos":900}],[1,{"secs":0,"nanos":500}]]}}}"#)?;
+    let profile = StorageProfileMeta::from_meta(meta);
+    assert_eq!(profile.cost(1), Duration::from_nanos(500));
+    assert_eq!(profile.cost(100), Duration::from_nanos(900));
+    Ok(())
+  }
+
   #[test]
   fn affine_seq_test() {
     let profile = AffineStorageProfile::new(
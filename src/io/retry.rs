@@ -0,0 +1,423 @@
+use rand::Rng;
+use serde::Serialize;
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use url::Url;
+
+use crate::common::SharedBytes;
+use crate::common::error::GenericError;
+use crate::common::error::GResult;
+use crate::common::error::HttpStatusError;
+use crate::io::storage::Adaptor;
+use crate::io::storage::Range;
+use crate::io::storage::ReadRequest;
+
+
+/* Retry policy */
+
+pub type RetryableFn = fn(&GenericError) -> bool;
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+  max_attempts: usize,  // including the first attempt
+  initial_backoff: Duration,
+  max_backoff: Duration,
+  multiplier: f64,
+  jitter: f64,  // fraction of backoff randomly cut, in [0, 1]
+  retryable: RetryableFn,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy {
+      max_attempts: 5,
+      initial_backoff: Duration::from_millis(50),
+      max_backoff: Duration::from_secs(5),
+      multiplier: 2.0,
+      jitter: 0.5,
+      retryable: is_transient,
+    }
+  }
+}
+
+impl RetryPolicy {
+  pub fn no_retry() -> RetryPolicy {
+    RetryPolicy::default().max_attempts(1)
+  }
+
+  pub fn max_attempts(mut self, max_attempts: usize) -> RetryPolicy {
+    assert!(max_attempts > 0, "Require at least one attempt");
+    self.max_attempts = max_attempts;
+    self
+  }
+
+  pub fn backoff(mut self, initial_backoff: Duration, max_backoff: Duration, multiplier: f64) -> RetryPolicy {
+    assert!(multiplier >= 1.0, "Backoff must not shrink, got multiplier {}", multiplier);
+    self.initial_backoff = initial_backoff;
+    self.max_backoff = max_backoff;
+    self.multiplier = multiplier;
+    self
+  }
+
+  pub fn jitter(mut self, jitter: f64) -> RetryPolicy {
+    assert!((0.0..=1.0).contains(&jitter), "Jitter must be in [0, 1], got {}", jitter);
+    self.jitter = jitter;
+    self
+  }
+
+  pub fn retryable(mut self, retryable: RetryableFn) -> RetryPolicy {
+    self.retryable = retryable;
+    self
+  }
+
+  pub fn is_retryable(&self, error: &GenericError) -> bool {
+    (self.retryable)(error)
+  }
+
+  // backoff before the given retry (starting at 0), without jitter
+  pub fn backoff_of(&self, retry: usize) -> Duration {
+    let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(retry as i32);
+    Duration::from_secs_f64(backoff.min(self.max_backoff.as_secs_f64()))
+  }
+
+  fn jittered_backoff_of(&self, retry: usize) -> Duration {
+    let cut = rand::thread_rng().gen_range(0.0..=self.jitter);
+    self.backoff_of(retry).mul_f64(1.0 - cut)
+  }
+}
+
+fn is_retryable_status(status: u16) -> bool {
+  // request timeout, too many requests/throttled, and server-side errors
+  status == 408 || status == 429 || (500..600).contains(&status)
+}
+
+fn is_transient_source(error: &(dyn Error + 'static)) -> bool {
+  if let Some(e) = error.downcast_ref::<HttpStatusError>() {
+    is_retryable_status(e.status())
+  } else if let Some(e) = error.downcast_ref::<reqwest::Error>() {
+    match e.status() {
+      Some(status) => is_retryable_status(status.as_u16()),
+      None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+    }
+  } else if let Some(e) = error.downcast_ref::<azure_core::HttpError>() {
+    match e {
+      azure_core::HttpError::StatusCode { status, .. } => is_retryable_status(status.as_u16()),
+      azure_core::HttpError::ExecuteRequest(_) => true,
+      azure_core::HttpError::ReadBytes(_) => true,
+      azure_core::HttpError::StreamReset(_) => true,
+      _ => false,
+    }
+  } else if let Some(e) = error.downcast_ref::<std::io::Error>() {
+    matches!(
+      e.kind(),
+      std::io::ErrorKind::TimedOut
+        | std::io::ErrorKind::Interrupted
+        | std::io::ErrorKind::ConnectionReset
+        | std::io::ErrorKind::ConnectionAborted
+        | std::io::ErrorKind::BrokenPipe
+        | std::io::ErrorKind::UnexpectedEof
+    )
+  } else {
+    false
+  }
+}
+
+// default classification, walks the error chain for network and throttling failures
+pub fn is_transient(error: &GenericError) -> bool {
+  let mut source: Option<&(dyn Error + 'static)> = Some(error.as_ref());
+  while let Some(e) = source {
+    if is_transient_source(e) {
+      return true;
+    }
+    source = e.source();
+  }
+  false
+}
+
+fn is_timeout(error: &GenericError) -> bool {
+  let mut source: Option<&(dyn Error + 'static)> = Some(error.as_ref());
+  while let Some(e) = source {
+    let is_timeout = match (e.downcast_ref::<reqwest::Error>(), e.downcast_ref::<std::io::Error>()) {
+      (Some(e), _) => e.is_timeout(),
+      (_, Some(e)) => e.kind() == std::io::ErrorKind::TimedOut,
+      _ => false,
+    };
+    if is_timeout {
+      return true;
+    }
+    source = e.source();
+  }
+  false
+}
+
+
+/* Retry statistics, shared so experiments can report them */
+
+#[derive(Serialize, Default, Clone, Debug, PartialEq)]
+pub struct RetryStats {
+  pub requests: usize,  // calls into the adaptor
+  pub attempts: usize,  // including retries
+  pub retries: usize,
+  pub timeouts: usize,  // failed attempts due to timeout
+  pub failures: usize,  // requests given up, either exhausted or not retryable
+}
+
+pub type SharedRetryStats = Arc<Mutex<RetryStats>>;
+
+pub fn new_retry_stats() -> SharedRetryStats {
+  Arc::new(Mutex::new(RetryStats::default()))
+}
+
+
+/* Retry adaptor, retries transient failures of the inner adaptor */
+
+#[derive(Debug)]
+pub struct RetryAdaptor {
+  inner: Box<dyn Adaptor>,
+  policy: RetryPolicy,
+  stats: SharedRetryStats,
+}
+
+impl RetryAdaptor {
+  pub fn new(inner: Box<dyn Adaptor>, policy: RetryPolicy, stats: SharedRetryStats) -> RetryAdaptor {
+    RetryAdaptor { inner, policy, stats }
+  }
+
+  fn retry<T, F>(&self, url: &Url, mut attempt_fn: F) -> GResult<T>
+  where F: FnMut() -> GResult<T> {
+    self.stats.lock().unwrap().requests += 1;
+    let mut retry = 0;
+    loop {
+      self.stats.lock().unwrap().attempts += 1;
+      let error = match attempt_fn() {
+        Ok(response) => return Ok(response),
+        Err(error) => error,
+      };
+      if is_timeout(&error) {
+        self.stats.lock().unwrap().timeouts += 1;
+      }
+      if retry + 1 >= self.policy.max_attempts || !self.policy.is_retryable(&error) {
+        self.stats.lock().unwrap().failures += 1;
+        return Err(error);
+      }
+      let backoff = self.policy.jittered_backoff_of(retry);
+      log::warn!("Retrying request to {} in {:?} after attempt {} failed, {}", url, backoff, retry + 1, error);
+      std::thread::sleep(backoff);
+      self.stats.lock().unwrap().retries += 1;
+      retry += 1;
+    }
+  }
+
+  fn batch_url(requests: &[ReadRequest]) -> Url {
+    // representative url for logging
+    requests.first()
+      .map(|request| request.url().clone())
+      .unwrap_or_else(|| Url::parse("batch:empty").unwrap())
+  }
+}
+
+impl Adaptor for RetryAdaptor {
+  fn read_all(&self, url: &Url) -> GResult<SharedBytes> {
+    self.retry(url, || self.inner.read_all(url))
+  }
+
+  fn read_range(&self, url: &Url, range: &Range) -> GResult<SharedBytes> {
+    self.retry(url, || self.inner.read_range(url, range))
+  }
+
+  fn read_in_place(&self, url: &Url, range: &Range, buffer: &mut [u8]) -> GResult<()> {
+    self.retry(url, || self.inner.read_in_place(url, range, buffer))
+  }
+
+  fn read_ranges(&self, url: &Url, ranges: &[Range]) -> GResult<Vec<SharedBytes>> {
+    // keep inner's vectored read, retry the whole set on failure
+    self.retry(url, || self.inner.read_ranges(url, ranges))
+  }
+
+  fn read_batch(&self, requests: &[ReadRequest]) -> GResult<Vec<SharedBytes>> {
+    self.retry(&RetryAdaptor::batch_url(requests), || self.inner.read_batch(requests))
+  }
+
+  // writes and removes are idempotent on remote storages
+
+  fn create(&self, url: &Url) -> GResult<()> {
+    self.retry(url, || self.inner.create(url))
+  }
+
+  fn write_all(&self, url: &Url, buf: &[u8]) -> GResult<()> {
+    self.retry(url, || self.inner.write_all(url, buf))
+  }
+
+  fn remove(&self, url: &Url) -> GResult<()> {
+    self.retry(url, || self.inner.remove(url))
+  }
+
+  fn version(&self, url: &Url) -> GResult<Option<String>> {
+    self.retry(url, || self.inner.version(url))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::AtomicUsize;
+  use std::sync::atomic::Ordering;
+
+  use crate::common::error::OpenUrlError;
+  use crate::io::storage::adaptor_test::fsa_tempdir_setup;
+  use crate::io::storage::adaptor_test::write_read_generic_random_ok;
+  use crate::io::storage::HttpAdaptor;
+  use crate::io::storage::http_client_with_timeout;
+  use crate::io::storage::url_from_dir_path;
+
+  // fails the first few calls with the given status, then passes through
+  #[derive(Debug)]
+  struct FlakyAdaptor {
+    inner: Box<dyn Adaptor>,
+    remaining_failures: AtomicUsize,
+    status: u16,
+  }
+
+  impl FlakyAdaptor {
+    fn new(inner: Box<dyn Adaptor>, num_failures: usize, status: u16) -> FlakyAdaptor {
+      FlakyAdaptor { inner, remaining_failures: AtomicUsize::new(num_failures), status }
+    }
+
+    fn maybe_fail(&self, url: &Url) -> GResult<()> {
+      let failing = self.remaining_failures
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| remaining.checked_sub(1))
+        .is_ok();
+      if failing {
+        return Err(HttpStatusError::boxed(url.to_string(), self.status, "flaky".to_string()));
+      }
+      Ok(())
+    }
+  }
+
+  impl Adaptor for FlakyAdaptor {
+    fn read_all(&self, url: &Url) -> GResult<SharedBytes> {
+      self.maybe_fail(url)?;
+      self.inner.read_all(url)
+    }
+
+    fn read_range(&self, url: &Url, range: &Range) -> GResult<SharedBytes> {
+      self.maybe_fail(url)?;
+      self.inner.read_range(url, range)
+    }
+
+    fn read_in_place(&self, url: &Url, range: &Range, buffer: &mut [u8]) -> GResult<()> {
+      self.maybe_fail(url)?;
+      self.inner.read_in_place(url, range, buffer)
+    }
+
+    fn create(&self, url: &Url) -> GResult<()> {
+      self.inner.create(url)
+    }
+
+    fn write_all(&self, url: &Url, buf: &[u8]) -> GResult<()> {
+      self.inner.write_all(url, buf)
+    }
+
+    fn remove(&self, url: &Url) -> GResult<()> {
+      self.inner.remove(url)
+    }
+  }
+
+  fn fast_policy() -> RetryPolicy {
+    RetryPolicy::default().backoff(Duration::from_millis(1), Duration::from_millis(4), 2.0)
+  }
+
+  #[test]
+  fn rtya_write_read_generic_random_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let rtya = RetryAdaptor::new(Box::new(fsa), fast_policy(), new_retry_stats());
+    write_read_generic_random_ok(rtya, &url_from_dir_path(temp_dir.path())?)
+  }
+
+  #[test]
+  fn rtya_retry_transient_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let test_path = url_from_dir_path(temp_dir.path())?.join("test.bin")?;
+    fsa.write_all(&test_path, &[3u8; 64])?;
+    let stats = new_retry_stats();
+    let rtya = RetryAdaptor::new(Box::new(FlakyAdaptor::new(Box::new(fsa), 2, 503)), fast_policy(), Arc::clone(&stats));
+
+    let buffer = rtya.read_range(&test_path, &Range { offset: 8, length: 8 })?;
+    assert_eq!(&[3u8; 8], &buffer[..]);
+    assert_eq!(*stats.lock().unwrap(), RetryStats { requests: 1, attempts: 3, retries: 2, timeouts: 0, failures: 0 });
+    Ok(())
+  }
+
+  #[test]
+  fn rtya_exhausted_err() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let test_path = url_from_dir_path(temp_dir.path())?.join("test.bin")?;
+    fsa.write_all(&test_path, &[3u8; 64])?;
+    let stats = new_retry_stats();
+    let flaky = FlakyAdaptor::new(Box::new(fsa), 5, 429);
+    let rtya = RetryAdaptor::new(Box::new(flaky), fast_policy().max_attempts(3), Arc::clone(&stats));
+
+    assert!(matches!(rtya.read_all(&test_path), Err(e) if e.is::<HttpStatusError>()));
+    assert_eq!(*stats.lock().unwrap(), RetryStats { requests: 1, attempts: 3, retries: 2, timeouts: 0, failures: 1 });
+    Ok(())
+  }
+
+  #[test]
+  fn rtya_not_retryable_err() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let test_path = url_from_dir_path(temp_dir.path())?.join("test.bin")?;
+    let stats = new_retry_stats();
+    let flaky = FlakyAdaptor::new(Box::new(fsa), 1, 404);
+    let rtya = RetryAdaptor::new(Box::new(flaky), fast_policy(), Arc::clone(&stats));
+
+    assert!(rtya.read_all(&test_path).is_err());
+    assert_eq!(*stats.lock().unwrap(), RetryStats { requests: 1, attempts: 1, retries: 0, timeouts: 0, failures: 1 });
+    Ok(())
+  }
+
+  #[test]
+  fn rtya_timeout_err() -> GResult<()> {
+    // server accepts connections but never responds
+    let server = tiny_http::Server::http("127.0.0.1:0")?;
+    let test_path = Url::parse(&format!("http://{}/test.bin", server.server_addr()))?;
+    let stats = new_retry_stats();
+    let httpa = HttpAdaptor::new().http_client(http_client_with_timeout(Duration::from_millis(50)));
+    let rtya = RetryAdaptor::new(Box::new(httpa), fast_policy().max_attempts(2), Arc::clone(&stats));
+
+    assert!(matches!(rtya.read_all(&test_path), Err(e) if is_timeout(&e) && is_transient(&e)));
+    assert_eq!(*stats.lock().unwrap(), RetryStats { requests: 1, attempts: 2, retries: 1, timeouts: 2, failures: 1 });
+    Ok(())
+  }
+
+  fn io_error(kind: std::io::ErrorKind) -> GenericError {
+    Box::new(std::io::Error::from(kind))
+  }
+
+  #[test]
+  fn transient_classification() {
+    let url = "s3://bucket/blob".to_string();
+    assert!(is_transient(&HttpStatusError::boxed(url.clone(), 500, String::new())));
+    assert!(is_transient(&HttpStatusError::boxed(url.clone(), 408, String::new())));
+    assert!(!is_transient(&HttpStatusError::boxed(url.clone(), 403, String::new())));
+    assert!(is_transient(&io_error(std::io::ErrorKind::ConnectionReset)));
+    assert!(is_timeout(&io_error(std::io::ErrorKind::TimedOut)));
+    assert!(!is_transient(&io_error(std::io::ErrorKind::NotFound)));
+    assert!(!is_transient(&OpenUrlError::boxed(url, "missing".to_string())));
+  }
+
+  #[test]
+  fn backoff_exponential_capped() {
+    let policy = RetryPolicy::default().backoff(Duration::from_millis(10), Duration::from_millis(50), 2.0);
+    assert_eq!(policy.backoff_of(0), Duration::from_millis(10));
+    assert_eq!(policy.backoff_of(2), Duration::from_millis(40));
+    assert_eq!(policy.backoff_of(3), Duration::from_millis(50));
+    for retry in 0..5 {
+      let backoff = policy.jittered_backoff_of(retry);
+      assert!(backoff <= policy.backoff_of(retry));
+      assert!(backoff >= policy.backoff_of(retry) / 2);
+    }
+  }
+}
@@ -0,0 +1,322 @@
+use serde::{Serialize, Deserialize};
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use url::Url;
+
+use crate::common::SharedBytes;
+use crate::common::error::GResult;
+use crate::io::profile::StorageProfile;
+use crate::io::storage::Adaptor;
+use crate::io::storage::Range;
+use crate::io::storage::ReadRequest;
+
+
+/* Trace events */
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TraceEvent {
+  pub url: String,
+  pub offset: usize,
+  pub length: usize,
+  pub start_ns: u128,  // since recorder started
+  pub end_ns: u128,
+  pub cache_hit: bool,
+  pub query: Option<usize>,
+}
+
+pub type SharedTraceRecorder = Arc<Mutex<TraceRecorder>>;
+
+// write trace events in jsonl, one event per line
+pub struct TraceRecorder {
+  writer: Box<dyn Write + Send>,
+  epoch: Instant,
+  query: Option<usize>,
+}
+
+impl std::fmt::Debug for TraceRecorder {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("TraceRecorder")
+      .field("epoch", &self.epoch)
+      .field("query", &self.query)
+      .finish()
+  }
+}
+
+impl TraceRecorder {
+  pub fn new(writer: Box<dyn Write + Send>) -> TraceRecorder {
+    TraceRecorder { writer, epoch: Instant::now(), query: None }
+  }
+
+  pub fn create(path: &Path) -> GResult<SharedTraceRecorder> {
+    let writer = BufWriter::new(File::create(path)?);
+    Ok(Arc::new(Mutex::new(TraceRecorder::new(Box::new(writer)))))
+  }
+
+  // tag following events with this query
+  pub fn begin_query(&mut self, query: usize) {
+    self.query = Some(query);
+  }
+
+  pub fn end_query(&mut self) {
+    self.query = None;
+  }
+
+  pub fn record(&mut self, url: &Url, range: &Range, start_time: Instant, cache_hit: bool) -> GResult<()> {
+    let event = TraceEvent {
+      url: url.to_string(),
+      offset: range.offset,
+      length: range.length,
+      start_ns: start_time.saturating_duration_since(self.epoch).as_nanos(),
+      end_ns: self.epoch.elapsed().as_nanos(),
+      cache_hit,
+      query: self.query,
+    };
+    serde_json::to_writer(&mut self.writer, &event)?;
+    self.writer.write_all(b"\n")?;
+    Ok(())
+  }
+
+  pub fn flush(&mut self) -> GResult<()> {
+    Ok(self.writer.flush()?)
+  }
+}
+
+pub fn read_trace(path: &Path) -> GResult<Vec<TraceEvent>> {
+  let reader = BufReader::new(File::open(path)?);
+  let mut events = Vec::new();
+  for line in reader.lines() {
+    let line = line?;
+    if !line.is_empty() {
+      events.push(serde_json::from_str(&line)?);
+    }
+  }
+  Ok(events)
+}
+
+
+/* Recording adaptor, logs every request to the inner adaptor */
+
+#[derive(Debug)]
+pub struct RecordingAdaptor {
+  inner: Box<dyn Adaptor>,
+  recorder: SharedTraceRecorder,
+}
+
+impl RecordingAdaptor {
+  pub fn new(inner: Box<dyn Adaptor>, recorder: SharedTraceRecorder) -> RecordingAdaptor {
+    RecordingAdaptor { inner, recorder }
+  }
+
+  fn record(&self, url: &Url, range: &Range, start_time: Instant) -> GResult<()> {
+    // requests reaching the adaptor missed the cache
+    self.recorder.lock().unwrap().record(url, range, start_time, false)
+  }
+}
+
+impl Adaptor for RecordingAdaptor {
+  fn read_all(&self, url: &Url) -> GResult<SharedBytes> {
+    let start_time = Instant::now();
+    let buffer = self.inner.read_all(url)?;
+    self.record(url, &Range { offset: 0, length: buffer.len() }, start_time)?;
+    Ok(buffer)
+  }
+
+  fn read_range(&self, url: &Url, range: &Range) -> GResult<SharedBytes> {
+    let start_time = Instant::now();
+    let buffer = self.inner.read_range(url, range)?;
+    self.record(url, range, start_time)?;
+    Ok(buffer)
+  }
+
+  fn read_in_place(&self, url: &Url, range: &Range, buffer: &mut [u8]) -> GResult<()> {
+    let start_time = Instant::now();
+    self.inner.read_in_place(url, range, buffer)?;
+    self.record(url, range, start_time)
+  }
+
+  fn read_ranges(&self, url: &Url, ranges: &[Range]) -> GResult<Vec<SharedBytes>> {
+    // keep inner's vectored read, all ranges share the same timestamps
+    let start_time = Instant::now();
+    let buffers = self.inner.read_ranges(url, ranges)?;
+    for range in ranges {
+      self.record(url, range, start_time)?;
+    }
+    Ok(buffers)
+  }
+
+  fn read_batch(&self, requests: &[ReadRequest]) -> GResult<Vec<SharedBytes>> {
+    let start_time = Instant::now();
+    let buffers = self.inner.read_batch(requests)?;
+    for (request, buffer) in requests.iter().zip(buffers.iter()) {
+      match request {
+        ReadRequest::All { url } => self.record(url, &Range { offset: 0, length: buffer.len() }, start_time)?,
+        ReadRequest::Range { url, range } => self.record(url, range, start_time)?,
+      }
+    }
+    Ok(buffers)
+  }
+
+  fn create(&self, url: &Url) -> GResult<()> {
+    self.inner.create(url)
+  }
+
+  fn write_all(&self, url: &Url, buf: &[u8]) -> GResult<()> {
+    self.inner.write_all(url, buf)
+  }
+
+  fn remove(&self, url: &Url) -> GResult<()> {
+    self.inner.remove(url)
+  }
+
+  fn version(&self, url: &Url) -> GResult<Option<String>> {
+    self.inner.version(url)
+  }
+}
+
+
+/* Replay against a storage profile */
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct QueryReplay {
+  pub query: usize,
+  pub num_reads: usize,
+  pub num_hits: usize,
+  pub read_bytes: usize,
+  pub measured_ns: u128,  // wall time covered by the query's reads
+  pub predicted_ns: u128,  // sequential cost of cache-missed reads
+}
+
+pub fn replay(events: &[TraceEvent], profile: &dyn StorageProfile) -> Vec<QueryReplay> {
+  // group events by query, ignoring those outside any query (e.g. setup)
+  let mut query_events: Vec<(usize, Vec<&TraceEvent>)> = Vec::new();
+  for event in events {
+    if let Some(query) = event.query {
+      match query_events.last_mut() {
+        Some((last_query, last_events)) if *last_query == query => last_events.push(event),
+        _ => query_events.push((query, vec![event])),
+      }
+    }
+  }
+
+  query_events.into_iter().map(|(query, events)| {
+    let miss_sizes: Vec<usize> = events.iter()
+      .filter(|event| !event.cache_hit)
+      .map(|event| event.length)
+      .collect();
+    QueryReplay {
+      query,
+      num_reads: miss_sizes.len(),
+      num_hits: events.len() - miss_sizes.len(),
+      read_bytes: miss_sizes.iter().sum(),
+      measured_ns: covered_ns(&events),
+      predicted_ns: profile.sequential_cost(&miss_sizes).as_nanos(),
+    }
+  }).collect()
+}
+
+fn covered_ns(events: &[&TraceEvent]) -> u128 {
+  // union of event intervals, concurrent reads overlap
+  let mut intervals: Vec<(u128, u128)> = events.iter()
+    .map(|event| (event.start_ns, event.end_ns))
+    .collect();
+  intervals.sort_unstable();
+  let mut covered = 0;
+  let mut current: Option<(u128, u128)> = None;
+  for (start, end) in intervals {
+    current = match current {
+      Some((current_start, current_end)) if start <= current_end => Some((current_start, current_end.max(end))),
+      Some((current_start, current_end)) => {
+        covered += current_end - current_start;
+        Some((start, end))
+      },
+      None => Some((start, end)),
+    };
+  }
+  if let Some((current_start, current_end)) = current {
+    covered += current_end - current_start;
+  }
+  covered
+}
+
+pub fn total_duration(replays: &[QueryReplay]) -> (Duration, Duration) {  // measured, predicted
+  let measured: u128 = replays.iter().map(|replay| replay.measured_ns).sum();
+  let predicted: u128 = replays.iter().map(|replay| replay.predicted_ns).sum();
+  (Duration::from_nanos(measured as u64), Duration::from_nanos(predicted as u64))
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::io::profile::AffineStorageProfile;
+  use crate::io::profile::Bandwidth;
+  use crate::io::profile::Latency;
+  use crate::io::storage::adaptor_test::fsa_tempdir_setup;
+  use crate::io::storage::url_from_dir_path;
+
+  fn event(query: Option<usize>, length: usize, start_ns: u128, end_ns: u128, cache_hit: bool) -> TraceEvent {
+    TraceEvent { url: "file:///test.bin".to_string(), offset: 0, length, start_ns, end_ns, cache_hit, query }
+  }
+
+  #[test]
+  fn record_replay_ok() -> GResult<()> {
+    let (temp_dir, fsa) = fsa_tempdir_setup()?;
+    let test_path = url_from_dir_path(temp_dir.path())?.join("test.bin")?;
+    let trace_path = temp_dir.path().join("trace.jsonl");
+    let recorder = TraceRecorder::create(&trace_path)?;
+    let rfsa = RecordingAdaptor::new(Box::new(fsa), Arc::clone(&recorder));
+    rfsa.write_all(&test_path, &[1u8; 4096])?;
+
+    // setup read, then two queries
+    rfsa.read_all(&test_path)?;
+    recorder.lock().unwrap().begin_query(0);
+    rfsa.read_range(&test_path, &Range { offset: 0, length: 100 })?;
+    rfsa.read_ranges(&test_path, &[Range { offset: 0, length: 10 }, Range { offset: 200, length: 20 }])?;
+    recorder.lock().unwrap().begin_query(1);
+    rfsa.read_range(&test_path, &Range { offset: 1000, length: 1000 })?;
+    recorder.lock().unwrap().record(&test_path, &Range { offset: 0, length: 100 }, Instant::now(), true)?;
+    recorder.lock().unwrap().flush()?;
+
+    let events = read_trace(&trace_path)?;
+    assert_eq!(events.len(), 6);
+    assert_eq!(events[0].query, None);
+    assert_eq!(events[0].length, 4096);
+    assert!(events.iter().all(|event| event.start_ns <= event.end_ns));
+
+    let profile = AffineStorageProfile::new(Latency::from_micros(1), Bandwidth::from_mbps(1.0));
+    let replays = replay(&events, &profile);
+    assert_eq!(replays.len(), 2);
+    assert_eq!((replays[0].query, replays[0].num_reads, replays[0].num_hits, replays[0].read_bytes), (0, 3, 0, 130));
+    assert_eq!(replays[0].predicted_ns, 133_000);
+    assert_eq!((replays[1].query, replays[1].num_reads, replays[1].num_hits, replays[1].read_bytes), (1, 1, 1, 1000));
+    assert_eq!(replays[1].predicted_ns, 1_001_000);
+    Ok(())
+  }
+
+  #[test]
+  fn replay_overlapping_measure_ok() {
+    let events = vec![
+      event(Some(0), 10, 0, 100, false),
+      event(Some(0), 10, 50, 120, false),  // concurrent with the previous
+      event(Some(0), 10, 200, 250, true),
+      event(None, 10, 300, 1000, false),
+      event(Some(1), 10, 1000, 1010, false),
+    ];
+    let replays = replay(&events, &Latency::from_nanos(7));
+    assert_eq!(replays.len(), 2);
+    assert_eq!(replays[0].measured_ns, 170);
+    assert_eq!(replays[0].predicted_ns, 14);
+    assert_eq!(replays[1].measured_ns, 10);
+    assert_eq!(replays[1].predicted_ns, 7);
+    assert_eq!(total_duration(&replays), (Duration::from_nanos(180), Duration::from_nanos(21)));
+  }
+}
@@ -1,6 +1,5 @@
 use serde::{Serialize, Deserialize};
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 use url::Url;
 
 use crate::common::error::GResult;
@@ -8,7 +7,7 @@ use crate::io::internal::ExternalStorage;
 
 
 pub struct Context {
-  pub storage: Option<Rc<RefCell<ExternalStorage>>>,
+  pub storage: Option<Arc<ExternalStorage>>,
   pub store_prefix: Option<Url>,
 }
 
@@ -38,13 +37,13 @@ impl Context {
     }
   }
 
-  pub fn put_storage(&mut self, storage: &Rc<RefCell<ExternalStorage>>) {
+  pub fn put_storage(&mut self, storage: &Arc<ExternalStorage>) {
     if let Some(storage) = &self.storage {
       // if exists, check same object
-      assert!(Rc::ptr_eq(storage, storage));
+      assert!(Arc::ptr_eq(storage, storage));
     } else {
       // if not, update
-      self.storage = Some(Rc::clone(storage));
+      self.storage = Some(Arc::clone(storage));
     }
   }
 
@@ -16,7 +16,7 @@ type MaybeKeyBuffer = Option<KeyBuffer>;
 
 /* Models */
 
-pub trait Model: Debug {
+pub trait Model: Debug + Send + Sync {
   // predict position(s) for the key
   fn predict(&self, key: &KeyT) -> KeyPositionRange;
 }
@@ -24,7 +24,7 @@ pub trait Model: Debug {
 
 /* Model Deserializer */
 
-pub trait ModelRecon: ModelReconMetaserde + Debug + Send {
+pub trait ModelRecon: ModelReconMetaserde + Debug + Send + Sync {
   fn reconstruct(&self, buffer: &[u8]) -> GResult<Box<dyn Model>>;
   fn get_load(&self) -> Vec<LoadDistribution>;
 
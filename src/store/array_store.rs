@@ -1,13 +1,13 @@
 use serde::{Serialize, Deserialize};
-use std::cell::RefCell;
 use std::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
 use url::Url;
 
 use crate::common::SharedByteView;
 use crate::common::error::GenericError;
 use crate::common::error::GResult;
 use crate::common::error::IncompleteDataStoreFromMeta;
+use crate::common::error::MismatchedDataSize;
 use crate::common::error::OutofCoverageError;
 use crate::io::internal::ExternalStorage;
 use crate::io::storage::Range;
@@ -17,7 +17,9 @@ use crate::store::DataStoreMeta;
 use crate::store::DataStoreMetaserde;
 use crate::store::DataStoreReader;
 use crate::store::DataStoreReaderIter;
+use crate::store::DataStoreScanner;
 use crate::store::DataStoreWriter;
+use crate::store::Readahead;
 use crate::store::key_buffer::KeyBuffer;
 use crate::store::key_position::KEY_LENGTH;
 use crate::store::key_position::KeyPositionCollection;
@@ -35,7 +37,7 @@ pub struct ArrayStoreState {
 
 
 pub struct ArrayStore {
-  storage: Rc<RefCell<ExternalStorage>>,
+  storage: Arc<ExternalStorage>,
   prefix_url: Url,
   state: ArrayStoreState,
   array_url: Url,
@@ -48,10 +50,10 @@ impl fmt::Debug for ArrayStore {
 }
 
 impl ArrayStore {
-  pub fn new_sized(storage: &Rc<RefCell<ExternalStorage>>, prefix_url: Url, array_name: String, data_size: usize) -> ArrayStore {
+  pub fn new_sized(storage: &Arc<ExternalStorage>, prefix_url: Url, array_name: String, data_size: usize) -> ArrayStore {
     let array_url = ArrayStore::array_url(&prefix_url, &array_name);
     ArrayStore{
-      storage: Rc::clone(storage),
+      storage: Arc::clone(storage),
       prefix_url,
       state: ArrayStoreState {
         array_name,
@@ -62,10 +64,10 @@ impl ArrayStore {
       array_url,
     }
   }
-  pub fn from_exact(storage: &Rc<RefCell<ExternalStorage>>, prefix_url: Url, array_name: String, data_size: usize, offset: usize, length: usize) -> ArrayStore {
+  pub fn from_exact(storage: &Arc<ExternalStorage>, prefix_url: Url, array_name: String, data_size: usize, offset: usize, length: usize) -> ArrayStore {
     let array_url = ArrayStore::array_url(&prefix_url, &array_name);
     ArrayStore{
-      storage: Rc::clone(storage),
+      storage: Arc::clone(storage),
       prefix_url,
       state: ArrayStoreState {
         array_name,
@@ -91,6 +93,15 @@ impl ArrayStore {
     self.state.data_size
   }
 
+  // number of elements
+  pub fn len(&self) -> usize {
+    self.state.length
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.state.length == 0
+  }
+
   pub fn read_all_size(&self) -> usize {
     self.state.length * self.state.data_size
   }
@@ -100,7 +111,7 @@ impl ArrayStore {
   }
 
   fn write_array(&self, array_buffer: &[u8]) -> GResult<()> {
-      self.storage.borrow().write_all(&self.array_url, array_buffer)
+      self.storage.write_all(&self.array_url, array_buffer)
   }
 
   fn read_page_range(&self, offset: PositionT, length: PositionT) -> GResult<(SharedByteView, usize)> {
@@ -116,7 +127,7 @@ impl ArrayStore {
     );
 
     // make read requests
-    let array_buffer = self.storage.borrow().read_range(
+    let array_buffer = self.storage.read_range(
       &self.array_url,
       &Range{
         offset: start_rank * self.state.data_size + self.state.offset,
@@ -135,10 +146,14 @@ impl DataStore for ArrayStore {
   fn begin_write(&mut self) -> GResult<Box<dyn DataStoreWriter + '_>> {
     // since we require mutable borrow, there will only be one writer in a code block.
     // this would disallow readers while the writer's lifetime as well
-    self.state.length = 0;  // TODO: append write?
+    self.state.length = 0;
     Ok(Box::new(ArrayStoreWriter::new(self)))
   }
 
+  fn begin_append(&mut self) -> GResult<Box<dyn DataStoreWriter + '_>> {
+    Ok(Box::new(ArrayStoreWriter::appending(self)?))
+  }
+
   fn read_all(&self) -> GResult<Box<dyn DataStoreReader>> {
     self.read_within(0, self.state.length * self.state.data_size)
   }
@@ -152,6 +167,15 @@ impl DataStore for ArrayStore {
   fn relevant_paths(&self) -> GResult<Vec<String>> {
     Ok(vec![self.state.array_name.clone()])
   }
+
+  fn scan_from(&self, offset: PositionT, readahead: Readahead) -> Box<dyn DataStoreScanner + '_> {
+    let start_rank = offset.div_ceil(self.state.data_size);
+    Box::new(ArrayStoreScanner::new(self, start_rank, readahead))
+  }
+
+  fn fixed_data_size(&self) -> Option<usize> {
+    Some(self.state.data_size)
+  }
 }
 
 impl DataStoreMetaserde for ArrayStore {  // for Metaserde
@@ -168,7 +192,7 @@ impl ArrayStore {  // for Metaserde
   }
 
   pub fn from_meta(meta: ArrayStoreState, ctx: &Context) -> GResult<ArrayStore> {
-    let storage = Rc::clone(ctx.storage.as_ref().expect("ArrayStore requires storage context"));
+    let storage = Arc::clone(ctx.storage.as_ref().expect("ArrayStore requires storage context"));
     let store_prefix = ctx.store_prefix.as_ref().ok_or_else(|| IncompleteDataStoreFromMeta::boxed("ArrayStore requires store prefix url"))?;
     let prefix_url = store_prefix.clone();
     let array_url = ArrayStore::array_url(&prefix_url, &meta.array_name);
@@ -190,6 +214,8 @@ pub struct ArrayStoreWriter<'a> {
 
   // writing state
   array_buffer: Vec<u8>,
+  header_length: usize,  // bytes before the first element in array_buffer
+  start_rank: usize,  // first rank of this write
 
   // temporary full index
   key_positions: KeyPositionCollection,
@@ -200,13 +226,36 @@ impl<'a> ArrayStoreWriter<'a> {
     ArrayStoreWriter{
       owner_store,
       array_buffer: Vec::new(),
+      header_length: 0,
+      start_rank: 0,
       key_positions: KeyPositionCollection::new(),
     }
   }
 
-  fn write_dbuffer(&mut self, dbuffer: &[u8]) -> GResult<PositionT> {
-    assert_eq!(dbuffer.len(), self.owner_store.state.data_size);
-    let cur_position = self.array_buffer.len();
+  // storage overwrites whole objects, so existing bytes are reloaded and rewritten with new elements
+  fn appending(owner_store: &mut ArrayStore) -> GResult<ArrayStoreWriter<'_>> {
+    let header_length = owner_store.state.offset;
+    let start_rank = owner_store.state.length;
+    let existing_length = header_length + start_rank * owner_store.state.data_size;
+    let array_buffer = if existing_length > 0 {
+      owner_store.storage.read_range(&owner_store.array_url, &Range { offset: 0, length: existing_length })?.clone_all()
+    } else {
+      Vec::new()
+    };
+    Ok(ArrayStoreWriter {
+      owner_store,
+      array_buffer,
+      header_length,
+      start_rank,
+      key_positions: KeyPositionCollection::new(),
+    })
+  }
+
+  fn write_dbuffer(&mut self, key: KeyT, dbuffer: &[u8]) -> GResult<PositionT> {
+    if dbuffer.len() != self.owner_store.state.data_size {
+      return Err(MismatchedDataSize::boxed(key, self.owner_store.state.data_size, dbuffer.len()));
+    }
+    let cur_position = self.array_buffer.len() - self.header_length;
     self.array_buffer.extend_from_slice(dbuffer);
     Ok(cur_position)
   }
@@ -219,16 +268,20 @@ impl<'a> ArrayStoreWriter<'a> {
 
 impl<'a> DataStoreWriter for ArrayStoreWriter<'a> {
   fn write(&mut self, kb: &KeyBuffer) -> GResult<()> {
-    let key_offset = self.write_dbuffer(&kb.serialize())?;
+    let key_offset = self.write_dbuffer(kb.key, &kb.serialize())?;
     self.key_positions.push(kb.key, key_offset);
     Ok(())
   }
 
   fn commit(mut self: Box<Self>) -> GResult<KeyPositionCollection> {
     let length = self.key_positions.len();
-    self.flush_array_buffer()?;
+    // appending nothing leaves the array untouched
+    if length > 0 || self.start_rank == 0 {
+      self.flush_array_buffer()?;
+    }
     self.owner_store.end_write(length);
-    self.key_positions.set_position_range(0, length * self.owner_store.state.data_size);
+    let data_size = self.owner_store.state.data_size;
+    self.key_positions.set_position_range(self.start_rank * data_size, (self.start_rank + length) * data_size);
     Ok(self.key_positions)
   }
 }
@@ -260,6 +313,34 @@ impl ArrayStoreReader {
     self.array_view.clone_all()
   }
 
+  // rank of the first element in this reader
+  pub fn start_rank(&self) -> usize {
+    self.start_rank
+  }
+
+  pub fn len(&self) -> usize {
+    self.array_view.len() / self.data_size
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // index of the first element whose key is not before, assuming keys sorted
+  pub fn partition_point<P: Fn(KeyT) -> bool>(&self, is_before: P) -> usize {
+    let mut l = 0;
+    let mut r = self.len();
+    while l < r {
+      let mid = l + (r - l) / 2;
+      if is_before(self.key_at(mid)) {
+        l = mid + 1;
+      } else {
+        r = mid;
+      }
+    }
+    l
+  }
+
   pub fn key_at(&self, idx: usize) -> KeyT {
     let offset = idx * self.data_size;
     let key_bytes = self.array_view.clone_within(offset .. offset + KEY_LENGTH);
@@ -332,6 +413,68 @@ impl<'a> Iterator for ArrayStoreReaderIter<'a> {
 }
 
 
+/* Scanner */
+
+pub struct ArrayStoreScanner<'a> {
+  owner_store: &'a ArrayStore,
+  readahead: Readahead,
+  next_rank: usize,  // first element not fetched yet
+  reader: Option<ArrayStoreReader>,
+  reader_idx: usize,
+}
+
+impl<'a> ArrayStoreScanner<'a> {
+  fn new(owner_store: &'a ArrayStore, start_rank: usize, readahead: Readahead) -> ArrayStoreScanner<'a> {
+    ArrayStoreScanner {
+      owner_store,
+      readahead,
+      next_rank: start_rank,
+      reader: None,
+      reader_idx: 0,
+    }
+  }
+
+  // false if all elements were fetched
+  fn fetch_more(&mut self) -> GResult<bool> {
+    let length = self.owner_store.state.length;
+    if self.next_rank >= length {
+      return Ok(false);
+    }
+    let data_size = self.owner_store.state.data_size;
+    let num_elements = std::cmp::min(
+      std::cmp::max(self.readahead.next_window() / data_size, 1),
+      length - self.next_rank,
+    );
+    self.reader = Some(self.owner_store.read_array_within(self.next_rank * data_size, num_elements * data_size)?);
+    self.reader_idx = 0;
+    self.next_rank += num_elements;
+    Ok(true)
+  }
+}
+
+impl<'a> DataStoreScanner for ArrayStoreScanner<'a> {}
+
+impl<'a> Iterator for ArrayStoreScanner<'a> {
+  type Item = GResult<KeyBuffer>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      if let Some(reader) = &self.reader {
+        if self.reader_idx < reader.array_view.len() / reader.data_size {
+          self.reader_idx += 1;
+          return Some(Ok(reader.kb_at(self.reader_idx - 1)));
+        }
+      }
+      match self.fetch_more() {
+        Ok(true) => continue,
+        Ok(false) => return None,
+        Err(e) => return Some(Err(e)),
+      }
+    }
+  }
+}
+
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -365,7 +508,7 @@ mod tests {
     let temp_dir = TempDir::new()?;
     let temp_dir_url = &url_from_dir_path(temp_dir.path())?;
     let fsa = FileSystemAdaptor::new();
-    let es = Rc::new(RefCell::new(ExternalStorage::new().with("file".to_string(), Box::new(fsa))?));
+    let es = Arc::new(ExternalStorage::new().with("file".to_string(), Box::new(fsa))?);
     let mut arrstore = ArrayStore::new_sized(
       &es,
       temp_dir_url.clone(),
@@ -455,4 +598,47 @@ mod tests {
 
     Ok(())
   }
+
+  #[test]
+  fn append_test() -> GResult<()> {
+    let (test_keys, test_buffers) = generate_simple_kv();
+    let temp_dir = TempDir::new()?;
+    let temp_dir_url = &url_from_dir_path(temp_dir.path())?;
+    let es = Arc::new(ExternalStorage::new().with("file".to_string(), Box::new(FileSystemAdaptor::new()))?);
+
+    // existing array behind a header, as in sosd blobs
+    let mut blob = vec![7u8; 8];
+    for (key, value) in test_keys.iter().zip(test_buffers.iter()).take(3) {
+      blob.extend_from_slice(&KeyBuffer::new(*key, value.to_vec()).serialize());
+    }
+    es.write_all(&temp_dir_url.join("test_arrstore")?, &blob)?;
+    let mut arrstore = ArrayStore::from_exact(&es, temp_dir_url.clone(), "test_arrstore".to_string(), 12, 8, 3);
+
+    for (start_idx, end_idx) in [(3, 4), (4, 4), (4, 10)] {
+      let kps = {
+        let mut bwriter = arrstore.begin_append()?;
+        for idx in start_idx..end_idx {
+          bwriter.write(&KeyBuffer::new(test_keys[idx], test_buffers[idx].to_vec()))?;
+        }
+        bwriter.commit()?
+      };
+      assert_eq!(kps.len(), end_idx - start_idx, "Expected only new entries");
+      assert_eq!(kps.whole_range(), (start_idx * 12, end_idx * 12));
+      for (kp, idx) in kps.iter().zip(start_idx..) {
+        assert_eq!(kp.position, idx * 12);
+      }
+    }
+    assert_eq!(arrstore.len(), test_keys.len());
+
+    // header kept, all entries readable
+    assert_eq!(&es.read_all(&temp_dir_url.join("test_arrstore")?)?[..8], &[7u8; 8]);
+    let reader = arrstore.read_all()?;
+    let read_kbs: Vec<KeyBuffer> = reader.iter().collect();
+    assert_eq!(read_kbs.len(), test_keys.len());
+    for (idx, kb) in read_kbs.iter().enumerate() {
+      assert_eq!(kb.key, test_keys[idx]);
+      assert_eq!(&kb.buffer[..], test_buffers[idx]);
+    }
+    Ok(())
+  }
 }
@@ -1,7 +1,6 @@
 use serde::{Serialize, Deserialize};
-use std::cell::RefCell;
 use std::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
 use url::Url;
 
 use crate::common::SharedByteView;
@@ -11,14 +10,17 @@ use crate::common::error::IncompleteDataStoreFromMeta;
 use crate::common::error::OutofCoverageError;
 use crate::io::internal::ExternalStorage;
 use crate::io::storage::Range;
+use crate::io::storage::ReadRequest;
 use crate::meta::Context;
 use crate::store::DataStore;
 use crate::store::DataStoreMeta;
 use crate::store::DataStoreMetaserde;
 use crate::store::DataStoreReader;
 use crate::store::DataStoreReaderIter;
+use crate::store::DataStoreScanner;
 use crate::store::DataStoreWriter;
 use crate::store::KeyT;
+use crate::store::Readahead;
 use crate::store::key_buffer::KeyBuffer;
 use crate::store::key_position::KeyPositionCollection;
 use crate::store::key_position::PositionT;
@@ -78,7 +80,7 @@ impl BlockStoreConfig {
     self
   }
 
-  pub fn build(self, storage: &Rc<RefCell<ExternalStorage>>, prefix_url: Url) -> BlockStore {
+  pub fn build(self, storage: &Arc<ExternalStorage>, prefix_url: Url) -> BlockStore {
     BlockStore::new(storage, prefix_url, self)
   }
 }
@@ -90,7 +92,7 @@ pub struct BlockStoreState {
 }
 
 pub struct BlockStore {
-  storage: Rc<RefCell<ExternalStorage>>,
+  storage: Arc<ExternalStorage>,
   prefix_url: Url,
   state: BlockStoreState,
 }
@@ -102,9 +104,9 @@ impl fmt::Debug for BlockStore {
 }
 
 impl BlockStore {
-  fn new(storage: &Rc<RefCell<ExternalStorage>>, prefix_url: Url, cfg: BlockStoreConfig) -> BlockStore {
+  fn new(storage: &Arc<ExternalStorage>, prefix_url: Url, cfg: BlockStoreConfig) -> BlockStore {
     BlockStore{
-      storage: Rc::clone(storage),
+      storage: Arc::clone(storage),
       prefix_url,
       state: BlockStoreState {
         cfg,
@@ -139,7 +141,7 @@ impl BlockStore {
 
   fn write_block(&self, block_idx: usize, block_buffer: &[u8]) -> GResult<()> {
       let block_url = self.block_url(block_idx)?;
-      self.storage.borrow().write_all(&block_url, block_buffer)
+      self.storage.write_all(&block_url, block_buffer)
   }
 
   fn read_page_range(&self, offset: PositionT, length: PositionT) -> GResult<(Vec<FlagT>, Vec<u8>)> {
@@ -152,6 +154,11 @@ impl BlockStore {
     let section_buffers = self.read_page_range_section(start_page_idx, end_page_idx)?;
     let mut flags = Vec::new();
     let mut chunks_buffer = Vec::new();
+    self.append_pages(&section_buffers, &mut flags, &mut chunks_buffer);
+    Ok((flags, chunks_buffer))
+  }
+
+  fn append_pages(&self, section_buffers: &[SharedByteView], flags: &mut Vec<FlagT>, chunks_buffer: &mut Vec<u8>) {
     for section_buffer in section_buffers {
       assert_eq!(section_buffer.len() % self.state.cfg.page_size, 0);
       // TODO: remove clone_all
@@ -161,13 +168,12 @@ impl BlockStore {
         chunks_buffer.extend(chunk);
       }
     }
-    Ok((flags, chunks_buffer))
   }
 
   fn read_page_range_section(&self, mut start_page_idx: usize, end_page_idx: usize) -> GResult<Vec<SharedByteView>> {
     let pages_per_block = self.state.cfg.block_size / self.state.cfg.page_size;
     let mut start_block_idx = start_page_idx / pages_per_block;
-    let mut section_buffers = Vec::new();
+    let mut section_requests = Vec::new();
     while start_page_idx < end_page_idx {
       // calculate current section boundaries
       let start_section_offset = (start_page_idx % pages_per_block) * self.state.cfg.page_size;
@@ -181,17 +187,18 @@ impl BlockStore {
       let section_length = (end_section_page_idx - start_page_idx) * self.state.cfg.page_size;
 
       // add read request for this section
-      let section_buffer = self.storage.borrow().read_range(
-        &self.block_url(start_block_idx)?,
-        &Range{ offset: start_section_offset, length: section_length },
-      )?;
-      section_buffers.push(section_buffer);
+      section_requests.push(ReadRequest::Range {
+        url: self.block_url(start_block_idx)?,
+        range: Range{ offset: start_section_offset, length: section_length },
+      });
 
       // step forward
       start_page_idx = end_section_page_idx;
       start_block_idx += 1;
     }
-    Ok(section_buffers)
+
+    // sections spanning multiple blocks are fetched together
+    self.storage.read_batch(&section_requests)
   }
 }
 
@@ -199,10 +206,14 @@ impl DataStore for BlockStore {
   fn begin_write(&mut self) -> GResult<Box<dyn DataStoreWriter + '_>> {
     // since we require mutable borrow, there will only be one writer in a code block.
     // this would disallow readers while the writer's lifetime as well
-    self.state.total_pages = 0;  // TODO: append write?
+    self.state.total_pages = 0;
     Ok(Box::new(BlockStoreWriter::new(self)))
   }
 
+  fn begin_append(&mut self) -> GResult<Box<dyn DataStoreWriter + '_>> {
+    Ok(Box::new(BlockStoreWriter::appending(self)?))
+  }
+
   fn read_all(&self) -> GResult<Box<dyn DataStoreReader>> {
     self.read_within(0, self.state.total_pages * self.state.cfg.page_size)
   }
@@ -219,6 +230,15 @@ impl DataStore for BlockStore {
     let num_blocks = total_size / self.state.cfg.block_size + (total_size % self.state.cfg.block_size != 0) as usize;
     Ok((0..num_blocks).map(|block_idx| self.block_path(block_idx)).collect())
   }
+
+  fn scan_from(&self, offset: PositionT, readahead: Readahead) -> Box<dyn DataStoreScanner + '_> {
+    let start_page_idx = offset.div_ceil(self.state.cfg.page_size);
+    Box::new(BlockStoreScanner::new(self, start_page_idx, readahead))
+  }
+
+  fn fixed_data_size(&self) -> Option<usize> {
+    None
+  }
 }
 
 impl DataStoreMetaserde for BlockStore {  // for Metaserde
@@ -231,7 +251,7 @@ impl DataStoreMetaserde for BlockStore {  // for Metaserde
 
 impl BlockStore {  // for Metaserde
   pub fn from_meta(meta: BlockStoreState, ctx: &Context) -> GResult<BlockStore> {
-    let storage = Rc::clone(ctx.storage.as_ref().expect("BlockStore requires storage context"));
+    let storage = Arc::clone(ctx.storage.as_ref().expect("BlockStore requires storage context"));
     let store_prefix = ctx.store_prefix.as_ref().ok_or_else(|| IncompleteDataStoreFromMeta::boxed("BlockStore requires store prefix url"))?;
     Ok(BlockStore{
       storage, 
@@ -251,6 +271,7 @@ pub struct BlockStoreWriter<'a> {
   block_buffer: Vec<u8>,
   block_idx: usize,
   page_idx: usize,
+  start_page_idx: usize,  // first page of this write
 
   // shortcuts for calculation
   chunk_size: usize,
@@ -270,12 +291,30 @@ impl<'a> BlockStoreWriter<'a> {
       block_buffer,
       block_idx: 0,
       page_idx: 0,
+      start_page_idx: 0,
       chunk_size,
       pages_per_block,
       key_positions: KeyPositionCollection::new(),
     }
   }
 
+  // continue from the last page, reloading pages of the partially filled last block
+  fn appending(owner_store: &mut BlockStore) -> GResult<BlockStoreWriter<'_>> {
+    let start_page_idx = owner_store.state.total_pages;
+    let page_size = owner_store.state.cfg.page_size;
+    let mut writer = BlockStoreWriter::new(owner_store);
+    writer.block_idx = start_page_idx / writer.pages_per_block;
+    writer.page_idx = start_page_idx;
+    writer.start_page_idx = start_page_idx;
+    let filled_length = (start_page_idx % writer.pages_per_block) * page_size;
+    if filled_length > 0 {
+      let block_url = writer.owner_store.block_url(writer.block_idx)?;
+      let filled_pages = writer.owner_store.storage.read_range(&block_url, &Range { offset: 0, length: filled_length })?;
+      writer.block_buffer[..filled_length].copy_from_slice(&filled_pages.clone_all());
+    }
+    Ok(writer)
+  }
+
   fn write_dbuffer(&mut self, dbuffer: &[u8]) -> GResult<PositionT> {
     let key_offset = self.page_idx * self.owner_store.state.cfg.page_size;
     let mut flag = FlagT::try_from(dbuffer.len()).ok().unwrap();
@@ -336,9 +375,13 @@ impl<'a> DataStoreWriter for BlockStoreWriter<'a> {
   }
 
   fn commit(mut self: Box<Self>) -> GResult<KeyPositionCollection> {
-    self.flush_current_block()?;
-    self.owner_store.end_write(self.page_idx);
-    self.key_positions.set_position_range(0, self.page_idx * self.owner_store.state.cfg.page_size);
+    // appending nothing leaves blocks untouched
+    if self.page_idx > self.start_page_idx || self.start_page_idx == 0 {
+      self.flush_current_block()?;
+    }
+    self.owner_store.end_write(self.page_idx - self.start_page_idx);
+    let page_size = self.owner_store.state.cfg.page_size;
+    self.key_positions.set_position_range(self.start_page_idx * page_size, self.page_idx * page_size);
     Ok(self.key_positions)
   }
 }
@@ -395,7 +438,7 @@ impl<'a> BlockStoreReaderIter<'a> {
       let dbuffer_offset = self.chunk_idx * self.r.chunk_size;
       let dbuffer_length = usize::try_from(self.r.chunk_flags[self.chunk_idx]).ok().unwrap();
       assert_ne!(dbuffer_length, 0);
-      if dbuffer_offset + dbuffer_length < self.r.chunks_buffer.len() {
+      if dbuffer_offset + dbuffer_length <= self.r.chunks_buffer.len() {
         // move chunk index
         self.chunk_idx += dbuffer_length / self.r.chunk_size + (dbuffer_length % self.r.chunk_size != 0) as usize;
 
@@ -422,6 +465,85 @@ impl<'a> Iterator for BlockStoreReaderIter<'a> {
 }
 
 
+/* Scanner */
+
+pub struct BlockStoreScanner<'a> {
+  owner_store: &'a BlockStore,
+  readahead: Readahead,
+  next_page_idx: usize,  // first page not fetched yet
+
+  // fetched pages, consumed up to chunk_idx
+  chunk_flags: Vec<FlagT>,
+  chunks_buffer: Vec<u8>,
+  chunk_idx: usize,
+}
+
+impl<'a> BlockStoreScanner<'a> {
+  fn new(owner_store: &'a BlockStore, start_page_idx: usize, readahead: Readahead) -> BlockStoreScanner<'a> {
+    BlockStoreScanner {
+      owner_store,
+      readahead,
+      next_page_idx: start_page_idx,
+      chunk_flags: Vec::new(),
+      chunks_buffer: Vec::new(),
+      chunk_idx: 0,
+    }
+  }
+
+  // false if all pages were fetched
+  fn fetch_more(&mut self) -> GResult<bool> {
+    let total_pages = self.owner_store.state.total_pages;
+    if self.next_page_idx >= total_pages {
+      return Ok(false);
+    }
+
+    // keep only the partially read key-buffer
+    let chunk_size = self.owner_store.chunk_size();
+    self.chunk_flags.drain(..self.chunk_idx);
+    self.chunks_buffer.drain(..self.chunk_idx * chunk_size);
+    self.chunk_idx = 0;
+
+    let num_pages = std::cmp::max(self.readahead.next_window() / self.owner_store.state.cfg.page_size, 1);
+    let end_page_idx = std::cmp::min(self.next_page_idx + num_pages, total_pages);
+    let section_buffers = self.owner_store.read_page_range_section(self.next_page_idx, end_page_idx)?;
+    self.owner_store.append_pages(&section_buffers, &mut self.chunk_flags, &mut self.chunks_buffer);
+    self.next_page_idx = end_page_idx;
+    Ok(true)
+  }
+}
+
+impl<'a> DataStoreScanner for BlockStoreScanner<'a> {}
+
+impl<'a> Iterator for BlockStoreScanner<'a> {
+  type Item = GResult<KeyBuffer>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let chunk_size = self.owner_store.chunk_size();
+    loop {
+      // skip continuation pages, e.g. when starting in the middle of a key-buffer
+      while self.chunk_idx < self.chunk_flags.len() && self.chunk_flags[self.chunk_idx] == CONT_FLAG {
+        self.chunk_idx += 1;
+      }
+      if self.chunk_idx < self.chunk_flags.len() {
+        let dbuffer_length = usize::try_from(self.chunk_flags[self.chunk_idx]).ok().unwrap();
+        let num_chunks = dbuffer_length / chunk_size + (dbuffer_length % chunk_size != 0) as usize;
+        if self.chunk_idx + num_chunks <= self.chunk_flags.len() {
+          let dbuffer_offset = self.chunk_idx * chunk_size;
+          let dbuffer = self.chunks_buffer[dbuffer_offset .. dbuffer_offset + dbuffer_length].to_vec();
+          self.chunk_idx += num_chunks;
+          return Some(Ok(KeyBuffer::deserialize(dbuffer)));
+        }
+      }
+      match self.fetch_more() {
+        Ok(true) => continue,
+        Ok(false) => return None,
+        Err(e) => return Some(Err(e)),
+      }
+    }
+  }
+}
+
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -474,7 +596,7 @@ mod tests {
     let temp_dir = TempDir::new()?;
     let temp_dir_url = &url_from_dir_path(temp_dir.path())?;
     let fsa = FileSystemAdaptor::new();
-    let es = Rc::new(RefCell::new(ExternalStorage::new().with("file".to_string(), Box::new(fsa))?));
+    let es = Arc::new(ExternalStorage::new().with("file".to_string(), Box::new(fsa))?);
     let mut bstore = BlockStore::builder("bstore".to_string())
       .block_size(128)  // tune down for unit testing
       .build(&es, temp_dir_url.clone());
@@ -561,4 +683,75 @@ mod tests {
 
     Ok(())
   }
+
+  #[test]
+  fn scan_across_blocks_test() -> GResult<()> {
+    let (test_keys, test_buffers) = generate_simple_kv();
+    let temp_dir = TempDir::new()?;
+    let temp_dir_url = &url_from_dir_path(temp_dir.path())?;
+    let es = Arc::new(ExternalStorage::new().with("file".to_string(), Box::new(FileSystemAdaptor::new()))?);
+    let mut bstore = BlockStore::builder("bstore".to_string())
+      .block_size(128)
+      .build(&es, temp_dir_url.clone());
+    let kps = {
+      let mut bwriter = bstore.begin_write()?;
+      for (key, value) in test_keys.iter().zip(test_buffers.iter()) {
+        bwriter.write(&KeyBuffer::new(*key, value.to_vec()))?;
+      }
+      bwriter.commit()?
+    };
+
+    // tiny windows split key-buffers across fetches, starting mid key-buffer skips it
+    for (offset, start_idx) in [(0, 0), (kps[1].position + 1, 2), (kps[13].position, 13)] {
+      let scanned = bstore.scan_from(offset, Readahead::new(1, 64)).collect::<GResult<Vec<KeyBuffer>>>()?;
+      assert_eq!(scanned.len(), test_keys.len() - start_idx);
+      for (kb, idx) in scanned.iter().zip(start_idx..) {
+        assert_eq!(kb.key, test_keys[idx]);
+        assert_eq!(&kb.buffer[..], &test_buffers[idx][..]);
+      }
+    }
+    assert!(bstore.scan_from(kps.total_bytes(), Readahead::new(1, 64)).next().is_none());
+    Ok(())
+  }
+
+  #[test]
+  fn append_test() -> GResult<()> {
+    let (test_keys, test_buffers) = generate_simple_kv();
+    let temp_dir = TempDir::new()?;
+    let temp_dir_url = &url_from_dir_path(temp_dir.path())?;
+    let es = Arc::new(ExternalStorage::new().with("file".to_string(), Box::new(FileSystemAdaptor::new()))?);
+    let mut bstore = BlockStore::builder("bstore".to_string())
+      .block_size(128)
+      .build(&es, temp_dir_url.clone());
+
+    // appends start mid-block and across block boundaries
+    let mut all_kps = Vec::new();
+    for (start_idx, end_idx) in [(0, 1), (1, 4), (4, 4), (4, 9), (9, 14)] {
+      let total_bytes = bstore.state.total_pages * bstore.state.cfg.page_size;
+      let kps = {
+        let mut bwriter = bstore.begin_append()?;
+        for idx in start_idx..end_idx {
+          bwriter.write(&KeyBuffer::new(test_keys[idx], test_buffers[idx].to_vec()))?;
+        }
+        bwriter.commit()?
+      };
+      assert_eq!(kps.len(), end_idx - start_idx, "Expected only new entries");
+      assert_eq!(kps.whole_range().0, total_bytes, "Expected positions after existing entries");
+      assert_eq!(kps.whole_range().1, bstore.state.total_pages * bstore.state.cfg.page_size);
+      all_kps.extend(kps.iter().map(|kp| (kp.key, kp.position)));
+    }
+
+    // same as a single write
+    let reader = bstore.read_all()?;
+    let read_kbs: Vec<KeyBuffer> = reader.iter().collect();
+    assert_eq!(read_kbs.len(), test_keys.len());
+    for (idx, kb) in read_kbs.iter().enumerate() {
+      assert_eq!(kb.key, test_keys[idx]);
+      assert_eq!(&kb.buffer[..], &test_buffers[idx][..]);
+    }
+    for (key, position) in all_kps {
+      assert_eq!(bstore.read_within(position, 1 << 12)?.iter().next().map(|kb| kb.key), Some(key));
+    }
+    Ok(())
+  }
 }
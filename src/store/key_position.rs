@@ -107,6 +107,22 @@ impl KeyPositionRange {
       length: right_offset.saturating_sub(left_offset),
     }
   }
+
+  // runs of consecutive ranges that overlap or touch, with their covering (offset, length)
+  pub fn group_overlapping(krs: &[KeyPositionRange]) -> Vec<(std::ops::Range<usize>, PositionT, PositionT)> {
+    let mut groups: Vec<(std::ops::Range<usize>, PositionT, PositionT)> = Vec::new();
+    for (idx, kr) in krs.iter().enumerate() {
+      match groups.last_mut() {
+        Some((idxs, offset_l, offset_r)) if kr.offset <= *offset_r && *offset_l <= kr.offset + kr.length => {
+          idxs.end = idx + 1;
+          *offset_l = cmp::min(*offset_l, kr.offset);
+          *offset_r = cmp::max(*offset_r, kr.offset + kr.length);
+        },
+        _ => groups.push((idx .. idx + 1, kr.offset, kr.offset + kr.length)),
+      }
+    }
+    groups.into_iter().map(|(idxs, offset_l, offset_r)| (idxs, offset_l, offset_r - offset_l)).collect()
+  }
 }
 
 
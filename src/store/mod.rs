@@ -8,11 +8,16 @@ use crate::store::key_position::KeyPositionCollection;
 use crate::store::key_position::KeyT;
 use crate::store::key_position::PositionT;
 
-pub trait DataStore: DataStoreMetaserde + Debug {
+pub trait DataStore: DataStoreMetaserde + Debug + Send + Sync {
   fn begin_write(&mut self) -> GResult<Box<dyn DataStoreWriter + '_>>;
+  // continues after existing entries, commit returns positions of only the new entries
+  fn begin_append(&mut self) -> GResult<Box<dyn DataStoreWriter + '_>>;
   fn read_all(&self) -> GResult<Box<dyn DataStoreReader>>;
   fn read_within(&self, offset: PositionT, length: PositionT) -> GResult<Box<dyn DataStoreReader>>;
   fn relevant_paths(&self) -> GResult<Vec<String>>;
+  fn scan_from(&self, offset: PositionT, readahead: Readahead) -> Box<dyn DataStoreScanner + '_>;
+  // serialized size that every entry must have, none if sizes may vary
+  fn fixed_data_size(&self) -> Option<usize>;
 }
 
 pub trait DataStoreWriter {
@@ -27,6 +32,29 @@ pub trait DataStoreReader {
 
 pub trait DataStoreReaderIter: Iterator<Item = KeyBuffer> {}
 
+// key-buffers from a position to the end of store, fetched window by window
+pub trait DataStoreScanner: Iterator<Item = GResult<KeyBuffer>> {}
+
+// read window size in bytes, doubling after each read up to max
+#[derive(Clone, Debug)]
+pub struct Readahead {
+  next: usize,
+  max: usize,
+}
+
+impl Readahead {
+  pub fn new(initial: usize, max: usize) -> Readahead {
+    let initial = std::cmp::max(initial, 1);
+    Readahead { next: initial, max: std::cmp::max(initial, max) }
+  }
+
+  pub fn next_window(&mut self) -> usize {
+    let window = self.next;
+    self.next = std::cmp::min(self.next.saturating_mul(2), self.max);
+    window
+  }
+}
+
 pub mod key_position;
 pub mod key_buffer;
 pub mod complexity;
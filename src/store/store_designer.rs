@@ -1,5 +1,4 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 use url::Url;
 
 use crate::io::internal::ExternalStorage;
@@ -10,12 +9,12 @@ use crate::store::KeyBuffer;
 
 
 pub struct StoreDesigner {
-  storage: Rc<RefCell<ExternalStorage>>,
+  storage: Arc<ExternalStorage>,
 }
 
 impl StoreDesigner {
-  pub fn new(storage: &Rc<RefCell<ExternalStorage>>) -> StoreDesigner {
-    StoreDesigner { storage: Rc::clone(storage) }
+  pub fn new(storage: &Arc<ExternalStorage>) -> StoreDesigner {
+    StoreDesigner { storage: Arc::clone(storage) }
   }
 
   pub fn design_for_kbs(&self, key_buffers: &[KeyBuffer], prefix_url: Url, store_name: String) -> Box<dyn DataStore> {